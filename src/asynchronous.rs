@@ -0,0 +1,268 @@
+use crate::cancellation::{CancellationToken, CHECK_INTERVAL};
+use crate::context::ExecutionContext;
+use crate::failsafe::{erase, take_value, Failsafe};
+use crate::failsafe_error::FailsafeError;
+use crate::policies::fallback::FallbackAble;
+use crate::policies::Policy;
+use crate::report::{ExecutionReport, PolicyError};
+use crate::run_state::PolicyActionState;
+use crate::Runnable;
+use std::any::Any;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::time::Instant;
+use tokio::time::{sleep, timeout};
+
+/// Async counterpart of `Runnable`, run with `Failsafe::run_async`.
+pub trait AsyncRunnable<T = (), E = Box<dyn Any + Send>>: Send {
+    fn run(&mut self) -> impl Future<Output = Result<T, E>> + Send;
+
+    #[allow(clippy::borrowed_box)]
+    fn update(&mut self, _other: &Box<dyn FallbackAble>) {}
+
+    // called before every attempt with the token of the attempt
+    fn set_cancellation_token(&mut self, _token: CancellationToken) {}
+
+    // called before every attempt with its context, the context can't be held across an await
+    // since the errors in it aren't `Sync`
+    fn set_context(&mut self, _context: &ExecutionContext) {}
+}
+
+type PolicyFuture<'a> = Pin<Box<dyn Future<Output = Result<(), FailsafeError>> + Send + 'a>>;
+
+impl Failsafe {
+    /// Runs `protected` with the same policies, without blocking the thread: delays and permit
+    /// waits are awaited, and timed out attempts are dropped at the deadline.
+    ///
+    /// Needs a tokio runtime with the time driver enabled.
+    pub async fn run_async<R, T, E>(&self, protected: &mut R) -> Result<T, FailsafeError<E>>
+    where
+        R: AsyncRunnable<T, E>,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        self.run_async_with_report(protected).await.0
+    }
+
+    /// Like `run_async`, stopping with `FailsafeError::Cancelled` once `token` is cancelled, e.g.
+    /// from another task.
+    pub async fn run_async_with_cancellation<R, T, E>(
+        &self,
+        protected: &mut R,
+        token: &CancellationToken,
+    ) -> Result<T, FailsafeError<E>>
+    where
+        R: AsyncRunnable<T, E>,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        self.run_async_typed(protected, token).await.0
+    }
+
+    /// Like `run_async`, also returning the errors the policies went through on the way.
+    pub async fn run_async_with_report<R, T, E>(
+        &self,
+        protected: &mut R,
+    ) -> (Result<T, FailsafeError<E>>, ExecutionReport)
+    where
+        R: AsyncRunnable<T, E>,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        self.run_async_typed(protected, &CancellationToken::new())
+            .await
+    }
+
+    async fn run_async_typed<R, T, E>(
+        &self,
+        protected: &mut R,
+        token: &CancellationToken,
+    ) -> (Result<T, FailsafeError<E>>, ExecutionReport)
+    where
+        R: AsyncRunnable<T, E>,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let mut execution = Execution {
+            runnable: protected,
+            value: None,
+            error: PhantomData,
+        };
+        let started = Instant::now();
+        let mut errors = vec![];
+        let mut policy = self.policy().fork();
+        policy.set_cancellation_token(token.child());
+        let k = run_policy(policy.as_mut(), &mut execution, &mut errors).await;
+        let report = self.complete(policy.as_ref(), started, &k, errors);
+        let result = k
+            .map_err(FailsafeError::typed)
+            .and_then(|_| execution.value.ok_or(FailsafeError::UnknownError));
+        (result, report)
+    }
+}
+
+// the runnable of an async execution, keeping the value of the last successful attempt
+struct Execution<'a, R, T, E> {
+    runnable: &'a mut R,
+    value: Option<T>,
+    error: PhantomData<fn() -> E>,
+}
+
+// async counterpart of `Policy::run`
+fn run_policy<'a, R, T, E>(
+    policy: &'a mut dyn Policy,
+    execution: &'a mut Execution<'_, R, T, E>,
+    policy_errors: &'a mut Vec<PolicyError>,
+) -> PolicyFuture<'a>
+where
+    R: AsyncRunnable<T, E>,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    Box::pin(async move {
+        let result = run_attempts(policy, execution, policy_errors).await;
+        policy.emit_outcome(&result);
+        result
+    })
+}
+
+// async counterpart of `Policy::run_attempts`
+async fn run_attempts<R, T, E>(
+    policy: &mut dyn Policy,
+    execution: &mut Execution<'_, R, T, E>,
+    policy_errors: &mut Vec<PolicyError>,
+) -> Result<(), FailsafeError>
+where
+    R: AsyncRunnable<T, E>,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    loop {
+        if policy.cancellation_token().is_cancelled() {
+            return Err(FailsafeError::Cancelled);
+        }
+        policy.start_attempt();
+        loop {
+            // listening before trying, so that a permit released in between isn't missed
+            let released = policy.permit_released();
+            let mut notified = released
+                .as_ref()
+                .map(|released| Box::pin(released.notified()));
+            if let Some(notified) = notified.as_mut() {
+                notified.as_mut().enable();
+            }
+            let Some(wait) = policy.try_before_run()? else {
+                break;
+            };
+            // waking up now and then to notice cancellations
+            let wait = wait.min(CHECK_INTERVAL);
+            match notified {
+                Some(notified) => {
+                    let _ = timeout(wait, notified).await;
+                }
+                None => sleep(wait).await,
+            }
+        }
+        if policy.cancellation_token().is_cancelled() {
+            policy.after_run();
+            return Err(FailsafeError::Cancelled);
+        }
+        let limit = policy.attempt_timeout();
+        if policy.inner().is_some() {
+            policy.prepare_inner();
+        } else {
+            execution
+                .runnable
+                .set_cancellation_token(policy.attempt_token());
+            execution
+                .runnable
+                .set_context(&policy.attempt_context(policy_errors));
+        }
+        let attempt = async {
+            match policy.inner_mut().as_mut() {
+                Some(inner) => run_policy(inner.as_mut(), execution, policy_errors).await,
+                None => match execution.runnable.run().await {
+                    Ok(value) => {
+                        execution.value = Some(value);
+                        Ok(())
+                    }
+                    Err(e) => Err(FailsafeError::RunnableError(erase(e))),
+                },
+            }
+        };
+        let result = match limit {
+            // dropping the attempt cancels it
+            Some(limit) => match timeout(limit, attempt).await {
+                Ok(result) => result,
+                Err(_) => {
+                    if let Some(inner) = policy.inner_mut().as_mut() {
+                        inner.abandon();
+                    }
+                    Err(FailsafeError::TimeoutError)
+                }
+            },
+            None => attempt.await,
+        };
+        policy.after_run();
+        let e = match result {
+            Ok(_) => {
+                policy.after_success();
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        let result = {
+            let mut runnable = SyncView { execution };
+            policy.policy_action(&mut Box::new(&mut runnable), &e)
+        };
+        if let Ok(PolicyActionState::Abort) = result {
+            return Err(e);
+        }
+        policy_errors.push(PolicyError {
+            policy: policy.name(),
+            attempt: policy.attempts(),
+            error: e,
+        });
+        return match result? {
+            PolicyActionState::Success => {
+                policy.reset();
+                Ok(())
+            }
+            PolicyActionState::Retry => {
+                if let Some(delay) = policy.take_retry_delay() {
+                    sleep(delay).await;
+                }
+                continue;
+            }
+            PolicyActionState::UsingFallback => Err(FailsafeError::UsedFallback),
+            _ => Ok(()),
+        };
+    }
+}
+
+// the synchronous side of an async runnable, for `Policy::policy_action`
+struct SyncView<'a, 'b, R, T, E> {
+    execution: &'a mut Execution<'b, R, T, E>,
+}
+
+impl<R, T, E> Runnable for SyncView<'_, '_, R, T, E>
+where
+    R: AsyncRunnable<T, E>,
+    T: 'static,
+{
+    // async runnables can't run on their own without blocking the runtime, a policy running the
+    // runnable itself gets an error instead
+    fn run(&mut self) -> Result<(), Box<dyn Any + Send>> {
+        let error: FailsafeError = FailsafeError::UnknownError;
+        Err(Box::new(error))
+    }
+
+    fn update(&mut self, other: &Box<dyn FallbackAble>) {
+        self.execution.runnable.update(other);
+    }
+
+    fn set_fallback_value(&mut self, value: Box<dyn Any>) -> bool {
+        take_value(&mut self.execution.value, value)
+    }
+}
@@ -0,0 +1,109 @@
+use crate::failsafe_error::FailsafeError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// how often a waiting execution checks whether it got cancelled
+pub(crate) const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Cooperative cancellation signal, shared by an execution, its policies and the runnable.
+///
+/// Clones share the same signal, so a token can be cancelled from another thread. Cancelling a
+/// token cancels its children as well, but not the other way around, e.g. a `TimeoutPolicy`
+/// cancels the attempt it guards without cancelling an outer `RetryPolicy`.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenInner>,
+}
+
+#[derive(Debug, Default)]
+struct TokenInner {
+    cancelled: AtomicBool,
+    // the token cancels itself once the deadline passes
+    deadline: Option<Instant>,
+    parent: Option<CancellationToken>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// A token that is cancelled along with this one.
+    pub fn child(&self) -> Self {
+        CancellationToken {
+            inner: Arc::new(TokenInner {
+                parent: Some(self.clone()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    /// A token that is cancelled along with this one, or once the `deadline` passes.
+    pub fn child_with_deadline(&self, deadline: Instant) -> Self {
+        CancellationToken {
+            inner: Arc::new(TokenInner {
+                deadline: Some(deadline),
+                parent: Some(self.clone()),
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+            || self
+                .inner
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+            || self
+                .inner
+                .parent
+                .as_ref()
+                .is_some_and(|parent| parent.is_cancelled())
+    }
+
+    // sleeps for `duration`, or until the token is cancelled
+    pub(crate) fn sleep(&self, duration: Duration) -> Result<(), FailsafeError> {
+        let end = Instant::now() + duration;
+        loop {
+            if self.is_cancelled() {
+                return Err(FailsafeError::Cancelled);
+            }
+            let now = Instant::now();
+            if now >= end {
+                return Ok(());
+            }
+            let mut wait = (end - now).min(CHECK_INTERVAL);
+            if let Some(deadline) = self.deadline() {
+                wait = wait.min(deadline.saturating_duration_since(now));
+            }
+            thread::sleep(wait);
+        }
+    }
+
+    // the earliest deadline of the token and its parents
+    fn deadline(&self) -> Option<Instant> {
+        let parent = self
+            .inner
+            .parent
+            .as_ref()
+            .and_then(|parent| parent.deadline());
+        match (self.inner.deadline, parent) {
+            (Some(deadline), Some(parent)) => Some(deadline.min(parent)),
+            (deadline, parent) => deadline.or(parent),
+        }
+    }
+}
+
+// tokens are equal when they share the same signal
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+}
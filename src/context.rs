@@ -0,0 +1,31 @@
+use crate::cancellation::CancellationToken;
+use crate::failsafe_error::FailsafeError;
+use std::time::{Duration, Instant};
+
+/// What's known about an execution when the runnable starts an attempt, passed to
+/// `Runnable::run_with_context`.
+#[derive(Debug)]
+pub struct ExecutionContext<'a> {
+    /// Attempts of the runnable in this execution, including the current one
+    pub attempt: u32,
+    /// When the execution started
+    pub started: Instant,
+    /// The last error the policies handled, e.g. the one being retried
+    pub last_error: Option<&'a FailsafeError>,
+    /// The runnable was updated by a `FallbackPolicy`, and runs with the fallback
+    pub fallback: bool,
+    /// The attempt is a trial execution of a half-open circuit breaker
+    pub trial: bool,
+    /// The token of the attempt
+    pub cancellation_token: CancellationToken,
+}
+
+impl ExecutionContext<'_> {
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation_token.is_cancelled()
+    }
+}
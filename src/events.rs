@@ -0,0 +1,126 @@
+use crate::failsafe_error::FailsafeError;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, ThreadId};
+use std::time::Duration;
+
+/// An event of an execution, passed to the event listeners of the policies and `Failsafe`.
+#[derive(Debug)]
+pub struct ExecutionEvent<'a> {
+    /// `Policy::name` of the policy the event comes from
+    pub policy: String,
+    /// Attempts the policy made in this execution, including the current one
+    pub attempt: u32,
+    /// Time since the policy's first attempt in this execution
+    pub elapsed: Duration,
+    /// The error the attempt, or the execution, failed with
+    pub error: Option<&'a FailsafeError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventKind {
+    Success,
+    Failure,
+    Retry,
+    RetriesExceeded,
+    Fallback,
+    Timeout,
+    Complete,
+}
+
+pub type EventListener = Box<dyn FnMut(&ExecutionEvent) + Send>;
+
+pub(crate) type EventListeners = Listeners<EventKind, dyn FnMut(&ExecutionEvent) + Send>;
+
+// listeners by the kind of event they're interested in, clones share the listeners
+//
+// Listeners are called without holding the lock of the list, so they are free to use the policies
+// and the `Failsafe` that called them, and a panicking listener doesn't break later executions. A
+// listener isn't called again for the events its own calls cause.
+pub(crate) struct Listeners<K, F: ?Sized> {
+    listeners: Arc<Mutex<Slots<K, F>>>,
+}
+
+type Slots<K, F> = Vec<(K, Arc<Slot<F>>)>;
+
+struct Slot<F: ?Sized> {
+    // the thread running the listener, if any
+    running_on: Mutex<Option<ThreadId>>,
+    listener: Mutex<Box<F>>,
+}
+
+impl<K, F: ?Sized> Clone for Listeners<K, F> {
+    fn clone(&self) -> Self {
+        Listeners {
+            listeners: self.listeners.clone(),
+        }
+    }
+}
+
+impl<K, F: ?Sized> Default for Listeners<K, F> {
+    fn default() -> Self {
+        Listeners {
+            listeners: Default::default(),
+        }
+    }
+}
+
+impl<K: PartialEq, F: ?Sized> Listeners<K, F> {
+    pub(crate) fn push(&self, kind: K, listener: Box<F>) {
+        let slot = Slot {
+            running_on: Mutex::new(None),
+            listener: Mutex::new(listener),
+        };
+        lock(&self.listeners).push((kind, Arc::new(slot)));
+    }
+
+    // calls the listeners of `kind` through `call`
+    pub(crate) fn call<C: FnMut(&mut F)>(&self, kind: K, mut call: C) {
+        let slots: Vec<_> = lock(&self.listeners)
+            .iter()
+            .filter(|(on, _)| *on == kind)
+            .map(|(_, slot)| slot.clone())
+            .collect();
+        for slot in slots {
+            slot.call(&mut call);
+        }
+    }
+}
+
+impl<F: ?Sized> Slot<F> {
+    fn call<C: FnMut(&mut F)>(&self, call: &mut C) {
+        let me = thread::current().id();
+        if *lock(&self.running_on) == Some(me) {
+            return;
+        }
+        let mut listener = lock(&self.listener);
+        *lock(&self.running_on) = Some(me);
+        let _running = Running(&self.running_on);
+        call(&mut listener);
+    }
+}
+
+// resets the running thread of a slot, even if the listener panics
+struct Running<'a>(&'a Mutex<Option<ThreadId>>);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        *lock(self.0) = None;
+    }
+}
+
+impl EventListeners {
+    // the event is only created when there's a listener for it
+    pub(crate) fn emit<'a, F: FnOnce() -> ExecutionEvent<'a>>(&self, kind: EventKind, event: F) {
+        let mut event = Some(event);
+        let mut created = None;
+        self.call(kind, |listener| {
+            let event = created.get_or_insert_with(|| event.take().unwrap()());
+            listener(event);
+        });
+    }
+}
+
+// a panicking listener leaves its lock poisoned, what it guards is still usable
+fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
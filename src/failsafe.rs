@@ -1,15 +1,21 @@
+use crate::cancellation::CancellationToken;
+use crate::context::ExecutionContext;
+use crate::events::{EventKind, EventListeners, ExecutionEvent};
 use crate::failsafe_error::FailsafeError;
+use crate::policies::fallback::FallbackAble;
+use crate::policies::timeout::Interruptable;
 use crate::policies::Policy;
-use crate::run_state::PolicyActionState;
+use crate::report::{ExecutionReport, PolicyError};
 use crate::Runnable;
 use std::any::Any;
-use std::borrow::{Borrow, BorrowMut};
-use std::error::Error;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+use std::time::Instant;
 
 /// Failsafe is a simple library for handling failures. It tries to resemble Failsafe for Java closely.
-
-type FailsafeRunnableResult = Result<Result<(), Box<dyn Any>>, FailsafeError>;
-
+///
+/// Policies can be configured further by chaining their builder methods after the arguments, e.g.
+/// `RetryPolicy; [3, Duration::from_millis(50)].with_backoff(Duration::from_secs(1), 2.0)`
 #[macro_export]
 macro_rules! failsafe {
     (
@@ -21,48 +27,255 @@ macro_rules! failsafe {
 
     ([
         $(
-            $x:tt; $( [ $( $y:expr ),* ])*
+            $x:tt; $( [ $( $y:expr ),* ])* $( .$m:ident $( ::<$( $t:ty ),*> )? ( $( $z:expr ),* ) )*
          ),*
      ]) => {
         Failsafe::builder()
-        $(.push(failsafe!($x; [$($( $y ),*),*])))*
+        $(.push(failsafe!($x; [$($( $y ),*),*]) $( .$m $( ::<$( $t ),*> )? ($( $z ),*) )*))*
         .build()
     }
 }
 
+/// A pipeline of policies protecting runnables.
+///
+/// `Failsafe` is `Send` and `Sync`, so it can be shared between threads, e.g. in an `Arc`. Every
+/// run executes its own copy of the pipeline, while the state that outlives runs, like circuit
+/// breaker counts, is shared by all of them. Clones share that state too.
 pub struct Failsafe {
     policy: Box<dyn Policy>,
-    state: PolicyActionState,
+    listeners: EventListeners,
+}
+
+impl Clone for Failsafe {
+    fn clone(&self) -> Self {
+        Failsafe {
+            policy: self.policy.fork(),
+            listeners: self.listeners.clone(),
+        }
+    }
 }
 
 impl Failsafe {
-    pub fn run<'a, T: Runnable>(&'a mut self, protected: &'a mut T) -> Result<(), FailsafeError> {
+    /// Runs `protected`, returning the value of its successful attempt, or of a
+    /// `FallbackPolicy::of_value`.
+    pub fn run<R, T, E>(&self, protected: &mut R) -> Result<T, FailsafeError<E>>
+    where
+        R: Runnable<T, E>,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        self.run_with_cancellation(protected, &CancellationToken::new())
+    }
+
+    /// Runs `protected`, stopping with `FailsafeError::Cancelled` once `token` is cancelled, e.g.
+    /// from another thread. Runnables get a child of the token, to stop early on their own.
+    pub fn run_with_cancellation<R, T, E>(
+        &self,
+        protected: &mut R,
+        token: &CancellationToken,
+    ) -> Result<T, FailsafeError<E>>
+    where
+        R: Runnable<T, E>,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        self.run_typed(protected, token).0
+    }
+
+    /// Like `run`, also returning the errors the policies went through on the way, e.g. timeouts
+    /// that were retried before falling back.
+    pub fn run_with_report<R, T, E>(
+        &self,
+        protected: &mut R,
+    ) -> (Result<T, FailsafeError<E>>, ExecutionReport)
+    where
+        R: Runnable<T, E>,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        self.run_typed(protected, &CancellationToken::new())
+    }
+
+    /// Runs a closure, instead of a `Runnable`.
+    pub fn run_fn<F, E>(&self, f: F) -> Result<(), FailsafeError<E>>
+    where
+        F: FnMut() -> Result<(), E>,
+        E: Send + 'static,
+    {
+        self.get(f)
+    }
+
+    /// Runs a closure, returning the value of its successful attempt, or of a
+    /// `FallbackPolicy::of_value`.
+    pub fn get<F, T, E>(&self, f: F) -> Result<T, FailsafeError<E>>
+    where
+        F: FnMut() -> Result<T, E>,
+        T: 'static,
+        E: Send + 'static,
+    {
+        self.get_with_report(f).0
+    }
+
+    /// Like `get`, also returning the errors the policies went through on the way.
+    pub fn get_with_report<F, T, E>(
+        &self,
+        mut f: F,
+    ) -> (Result<T, FailsafeError<E>>, ExecutionReport)
+    where
+        F: FnMut() -> Result<T, E>,
+        T: 'static,
+        E: Send + 'static,
+    {
+        self.get_erased(|_: &ExecutionContext| f(), &CancellationToken::new())
+    }
+
+    /// Like `get`, with a closure that takes the context of the attempt.
+    pub fn get_with_context<F, T, E>(&self, f: F) -> Result<T, FailsafeError<E>>
+    where
+        F: FnMut(&ExecutionContext) -> Result<T, E>,
+        T: 'static,
+        E: Send + 'static,
+    {
+        self.get_erased(f, &CancellationToken::new()).0
+    }
+
+    /// Like `get_with_context`, stopping with `FailsafeError::Cancelled` once `token` is cancelled.
+    /// The closure finds a child of the token in the context, to stop early on its own.
+    pub fn get_with_cancellation<F, T, E>(
+        &self,
+        f: F,
+        token: &CancellationToken,
+    ) -> Result<T, FailsafeError<E>>
+    where
+        F: FnMut(&ExecutionContext) -> Result<T, E>,
+        T: 'static,
+        E: Send + 'static,
+    {
+        self.get_erased(f, token).0
+    }
+
+    fn get_erased<F, T, E>(
+        &self,
+        f: F,
+        token: &CancellationToken,
+    ) -> (Result<T, FailsafeError<E>>, ExecutionReport)
+    where
+        F: FnMut(&ExecutionContext) -> Result<T, E>,
+        T: 'static,
+        E: Send + 'static,
+    {
+        let mut runnable = FnRunnable::new(f);
+        let (result, report) = self.run_erased(&mut runnable, token);
+        let result = result
+            .map_err(FailsafeError::typed)
+            .and_then(|_| runnable.value.ok_or(FailsafeError::UnknownError));
+        (result, report)
+    }
+
+    fn run_typed<R, T, E>(
+        &self,
+        protected: &mut R,
+        token: &CancellationToken,
+    ) -> (Result<T, FailsafeError<E>>, ExecutionReport)
+    where
+        R: Runnable<T, E>,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let mut runnable = Erased {
+            runnable: protected,
+            value: None,
+            error: PhantomData,
+        };
+        let (result, report) = self.run_erased(&mut runnable, token);
+        let result = result
+            .map_err(FailsafeError::typed)
+            .and_then(|_| runnable.value.ok_or(FailsafeError::UnknownError));
+        (result, report)
+    }
+
+    // runs the pipeline with the runnable the policies work with
+    fn run_erased(
+        &self,
+        runnable: &mut dyn Runnable,
+        token: &CancellationToken,
+    ) -> (Result<(), FailsafeError>, ExecutionReport) {
+        let started = Instant::now();
         let mut errors = vec![];
-        let k = self.policy.run(&mut Box::new(protected), &mut errors);
-        println!("{:?}", errors);
-        k
+        let mut policy = self.policy.fork();
+        policy.set_cancellation_token(token.child());
+        let k = policy.run(&mut Box::new(runnable), &mut errors);
+        let report = self.complete(policy.as_ref(), started, &k, errors);
+        (k, report)
     }
 
-    pub(crate) fn state(&self) -> PolicyActionState {
-        PolicyActionState::Success
+    // notifies the `on_complete` listeners, with the outermost policy's attempts
+    pub(crate) fn complete(
+        &self,
+        policy: &dyn Policy,
+        started: Instant,
+        result: &Result<(), FailsafeError>,
+        errors: Vec<PolicyError>,
+    ) -> ExecutionReport {
+        let elapsed = started.elapsed();
+        self.listeners.emit(EventKind::Complete, || ExecutionEvent {
+            policy: policy.name(),
+            attempt: policy.attempts(),
+            elapsed,
+            error: result.as_ref().err(),
+        });
+        ExecutionReport {
+            errors,
+            attempts: policy.attempts(),
+            elapsed,
+        }
     }
 
     pub fn builder() -> FailsafeBuilder {
         FailsafeBuilder::new()
     }
 
+    #[allow(clippy::borrowed_box)]
     pub fn policy(&self) -> &Box<dyn Policy> {
         &self.policy
     }
+
+    /// Finds the outermost policy of type `T` in the pipeline, e.g. to control a circuit breaker.
+    pub fn find_policy<T: Policy + 'static>(&self) -> Option<&T> {
+        let mut current = Some(&self.policy);
+        while let Some(policy) = current {
+            if let Some(policy) = policy.as_any().downcast_ref::<T>() {
+                return Some(policy);
+            }
+            current = policy.inner().as_ref();
+        }
+        None
+    }
+
+    /// Finds the outermost policy of type `T` in the pipeline.
+    pub fn find_policy_mut<T: Policy + 'static>(&mut self) -> Option<&mut T> {
+        let mut current = Some(&mut self.policy);
+        while let Some(policy) = current {
+            if policy.as_any().is::<T>() {
+                return policy.as_any_mut().downcast_mut::<T>();
+            }
+            current = policy.inner_mut().as_mut();
+        }
+        None
+    }
 }
 
 pub struct FailsafeBuilder {
     policies: Vec<Box<dyn Policy>>,
+    listeners: EventListeners,
 }
 
 impl FailsafeBuilder {
     fn new() -> FailsafeBuilder {
-        FailsafeBuilder { policies: vec![] }
+        FailsafeBuilder {
+            policies: vec![],
+            listeners: Default::default(),
+        }
     }
 }
 
@@ -72,24 +285,194 @@ impl FailsafeBuilder {
         self
     }
 
-    pub(crate) fn build(&mut self) -> Failsafe {
+    /// Calls `listener` once every execution completes, with its final error if it failed.
+    pub fn on_complete<F: FnMut(&ExecutionEvent) + Send + 'static>(
+        &mut self,
+        listener: F,
+    ) -> &mut Self {
+        self.listeners.push(EventKind::Complete, Box::new(listener));
+        self
+    }
+
+    pub fn build(&mut self) -> Failsafe {
         if self.policies.is_empty() {
             panic!("No policy or runnable provided.")
         }
         let mut first = self.policies.pop().unwrap();
-        while !self.policies.is_empty() {
-            let mut current = self.policies.pop().unwrap();
+        while let Some(mut current) = self.policies.pop() {
             current.set_inner(first);
             first = current;
         }
         Failsafe {
             policy: first,
-            state: PolicyActionState::Success,
+            listeners: self.listeners.clone(),
+        }
+    }
+}
+
+// boxes the error of a runnable for the policies, errors that are boxed already are passed as is,
+// so predicates like `RetryPolicy::handle` see the actual error
+pub(crate) fn erase<E: Send + 'static>(error: E) -> Box<dyn Any + Send> {
+    let error: Box<dyn Any + Send> = Box::new(error);
+    match error.downcast::<Box<dyn Any + Send>>() {
+        Ok(error) => *error,
+        Err(error) => error,
+    }
+}
+
+// takes a fallback value, if it's of the value type of the runnable
+pub(crate) fn take_value<T: 'static>(slot: &mut Option<T>, value: Box<dyn Any>) -> bool {
+    match value.downcast::<T>() {
+        Ok(value) => {
+            *slot = Some(*value);
+            true
         }
+        Err(_) => false,
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// adapts a closure to a runnable, keeping the value of the last successful attempt
+struct FnRunnable<F, T> {
+    f: F,
+    value: Option<T>,
+    started: Instant,
+    attempts: u32,
+    token: CancellationToken,
+}
+
+impl<F, T> FnRunnable<F, T> {
+    fn new(f: F) -> Self {
+        FnRunnable {
+            f,
+            value: None,
+            started: Instant::now(),
+            attempts: 0,
+            token: CancellationToken::new(),
+        }
+    }
+}
+
+impl<F, T, E> Runnable for FnRunnable<F, T>
+where
+    F: FnMut(&ExecutionContext) -> Result<T, E>,
+    T: 'static,
+    E: Send + 'static,
+{
+    // policies that run the closure without a context, give it what the runnable knows
+    fn run(&mut self) -> Result<(), Box<dyn Any + Send>> {
+        let context = ExecutionContext {
+            attempt: self.attempts + 1,
+            started: self.started,
+            last_error: None,
+            fallback: false,
+            trial: false,
+            cancellation_token: self.token.clone(),
+        };
+        self.run_with_context(&context)
+    }
+
+    fn run_with_context(&mut self, context: &ExecutionContext) -> Result<(), Box<dyn Any + Send>> {
+        self.attempts = context.attempt;
+        match (self.f)(context) {
+            Ok(value) => {
+                self.value = Some(value);
+                Ok(())
+            }
+            Err(e) => Err(erase(e)),
+        }
+    }
+
+    // closures have nothing to take from a fallback
+    fn update(&mut self, _: &Box<dyn FallbackAble>) {}
+
+    fn set_fallback_value(&mut self, value: Box<dyn Any>) -> bool {
+        take_value(&mut self.value, value)
+    }
+
+    fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.token = token;
+    }
+}
+
+// erases the value and error types of a runnable for the policies, keeping the value of the last
+// successful attempt
+struct Erased<'a, R, T, E> {
+    runnable: &'a mut R,
+    value: Option<T>,
+    error: PhantomData<fn() -> E>,
+}
+
+impl<R, T: 'static, E: Send + 'static> Erased<'_, R, T, E> {
+    fn keep(&mut self, result: Result<T, E>) -> Result<(), Box<dyn Any + Send>> {
+        match result {
+            Ok(value) => {
+                self.value = Some(value);
+                Ok(())
+            }
+            Err(e) => Err(erase(e)),
+        }
+    }
+}
+
+impl<R, T, E> Runnable for Erased<'_, R, T, E>
+where
+    R: Runnable<T, E>,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    fn run(&mut self) -> Result<(), Box<dyn Any + Send>> {
+        let result = self.runnable.run();
+        self.keep(result)
+    }
+
+    fn run_with_context(&mut self, context: &ExecutionContext) -> Result<(), Box<dyn Any + Send>> {
+        let result = self.runnable.run_with_context(context);
+        self.keep(result)
+    }
+
+    fn update(&mut self, other: &Box<dyn FallbackAble>) {
+        self.runnable.update(other);
+    }
+
+    fn set_fallback_value(&mut self, value: Box<dyn Any>) -> bool {
+        take_value(&mut self.value, value)
+    }
+
+    fn detach(&self) -> Option<Box<dyn Interruptable>> {
+        self.runnable.detach().map(|detached| {
+            Box::new(ErasedInterruptable {
+                detached,
+                value: Mutex::new(None),
+            }) as Box<dyn Interruptable>
+        })
+    }
+
+    fn attach(&mut self, detached: &Box<dyn Interruptable>) {
+        if let Some(erased) = detached.as_any().downcast_ref::<ErasedInterruptable<T>>() {
+            self.value = erased.value.lock().unwrap().take();
+            self.runnable.attach(&erased.detached);
+        }
+    }
+
+    fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self.runnable.set_cancellation_token(token);
+    }
+}
+
+// the detached copy of an erased runnable, the value is taken back when attached
+struct ErasedInterruptable<T> {
+    detached: Box<dyn Interruptable<T>>,
+    value: Mutex<Option<T>>,
+}
+
+impl<T: Send + 'static> Interruptable for ErasedInterruptable<T> {
+    fn run(&mut self) -> Result<(), Box<dyn Any + Send>> {
+        let value = self.detached.run()?;
+        *self.value.lock().unwrap() = Some(value);
+        Ok(())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
 }
@@ -1,8 +1,9 @@
 use std::any::Any;
 use thiserror::Error;
 
+/// Errors of an execution, `E` being the error type of the runnable
 #[derive(Error, Debug)]
-pub enum FailsafeError {
+pub enum FailsafeError<E = Box<dyn Any + Send>> {
     #[error("Just a dummy error")]
     DummyError,
     #[error("Timeout error")]
@@ -10,21 +11,57 @@ pub enum FailsafeError {
     #[error("Retry error")]
     RetryError,
     #[error("Runnable Error")]
-    RunnableError(Box<dyn Any>),
+    RunnableError(E),
     #[error("Used Fallback")]
     UsedFallback,
     #[error("Unknown Error")]
     UnknownError,
     #[error("Circuit Breaker Open")]
     CircuitBreakerOpen,
+    #[error("Rate Limit Exceeded")]
+    RateLimitExceeded,
+    #[error("Bulkhead Full")]
+    BulkheadFull,
+    #[error("Cancelled")]
+    Cancelled,
+    /// A fallback failed with an error that isn't of the runnable's error type
+    #[error("Fallback Error")]
+    FallbackError(Box<dyn Any + Send>),
 }
 
-impl FailsafeError {
+impl<E: 'static> FailsafeError<E> {
     pub fn as_any(&self) -> &dyn Any {
         self
     }
 
     pub fn from_any(other: &Box<dyn Any>) -> &Self {
-        other.downcast_ref::<FailsafeError>().unwrap()
+        other.downcast_ref::<FailsafeError<E>>().unwrap()
+    }
+}
+
+impl FailsafeError {
+    // converts the error of the policies, to the error type of the runnable
+    pub(crate) fn typed<E: Send + 'static>(self) -> FailsafeError<E> {
+        match self {
+            FailsafeError::RunnableError(e) => match e.downcast::<E>() {
+                Ok(e) => FailsafeError::RunnableError(*e),
+                // errors of `Box<dyn Any>` runnables are passed unwrapped
+                Err(e) => match (Box::new(e) as Box<dyn Any + Send>).downcast::<E>() {
+                    Ok(e) => FailsafeError::RunnableError(*e),
+                    // runnable errors are of its type, only fallbacks may fail with another one
+                    Err(e) => FailsafeError::FallbackError(*e.downcast().unwrap()),
+                },
+            },
+            FailsafeError::DummyError => FailsafeError::DummyError,
+            FailsafeError::TimeoutError => FailsafeError::TimeoutError,
+            FailsafeError::RetryError => FailsafeError::RetryError,
+            FailsafeError::UsedFallback => FailsafeError::UsedFallback,
+            FailsafeError::UnknownError => FailsafeError::UnknownError,
+            FailsafeError::CircuitBreakerOpen => FailsafeError::CircuitBreakerOpen,
+            FailsafeError::RateLimitExceeded => FailsafeError::RateLimitExceeded,
+            FailsafeError::BulkheadFull => FailsafeError::BulkheadFull,
+            FailsafeError::Cancelled => FailsafeError::Cancelled,
+            FailsafeError::FallbackError(e) => FailsafeError::FallbackError(e),
+        }
     }
 }
@@ -1,18 +1,55 @@
-use crate::failsafe_error::FailsafeError;
+use crate::cancellation::CancellationToken;
+use crate::context::ExecutionContext;
 use crate::policies::fallback::FallbackAble;
+use crate::policies::timeout::Interruptable;
 use std::any::Any;
-use std::error::Error;
-use std::time::Duration;
 
+#[cfg(feature = "async")]
+pub mod asynchronous;
+pub mod cancellation;
+pub mod context;
+pub mod events;
 pub mod failsafe;
 pub mod failsafe_error;
 pub mod policies;
+pub mod report;
 pub mod run_state;
 
 // all objects that are being protected should implement Executable trait
-pub trait Runnable {
-    fn run(&mut self) -> Result<(), Box<dyn Any>>;
+//
+// `T` is the value of a successful run, `E` the error of a failed one. Policies work with the
+// defaults, `Failsafe::run` converts the other runnables at the edge.
+pub trait Runnable<T = (), E = Box<dyn Any + Send>> {
+    fn run(&mut self) -> Result<T, E>;
+
+    // runs an attempt knowing about the execution so far, e.g. the attempt number and the last
+    // error, runnables that need it override this one instead of `run`
+    fn run_with_context(&mut self, _context: &ExecutionContext) -> Result<T, E> {
+        self.run()
+    }
+
+    #[allow(clippy::borrowed_box)]
     fn update(&mut self, other: &Box<dyn FallbackAble>);
+
+    // a copy that an interrupting TimeoutPolicy runs on another thread, runnables that can't be
+    // detached are timed out once they finish
+    fn detach(&self) -> Option<Box<dyn Interruptable<T>>> {
+        None
+    }
+
+    // takes over the state of the detached copy, once it finished in time
+    #[allow(clippy::borrowed_box)]
+    fn attach(&mut self, _detached: &Box<dyn Interruptable<T>>) {}
+
+    // takes the value of a `FallbackPolicy::of_value`, returning false if it can't be returned in
+    // place of the runnable's, `Failsafe::run` takes care of it
+    fn set_fallback_value(&mut self, _value: Box<dyn Any>) -> bool {
+        false
+    }
+
+    // called before every attempt with the token of the attempt, long running runnables can
+    // check `is_cancelled` and stop early
+    fn set_cancellation_token(&mut self, _token: CancellationToken) {}
 }
 
 #[cfg(test)]
@@ -1,3 +1,4 @@
+use crate::cancellation::CancellationToken;
 use crate::policies::fallback::FallbackAble;
 use crate::policies::timeout::Interruptable;
 use crate::Runnable;
@@ -13,6 +14,8 @@ use thiserror::Error;
 pub enum PersonError {
     #[error("Failed to find name for")]
     NameFindingError,
+    #[error("Cancelled while finding name")]
+    Cancelled,
 }
 
 impl PersonError {
@@ -20,7 +23,7 @@ impl PersonError {
         self
     }
 
-    pub fn from_any(other: &Box<dyn Any>) -> &Self {
+    pub fn from_any(other: &Box<dyn Any + Send>) -> &Self {
         other.downcast_ref::<PersonError>().unwrap()
     }
 }
@@ -33,6 +36,7 @@ pub struct Person {
     _fail_pattern: Option<Vec<bool>>,
     _bk_fail_pattern: Option<Vec<bool>>,
     _wait_for: Option<Duration>,
+    _token: CancellationToken,
 }
 
 impl Person {
@@ -41,6 +45,12 @@ impl Person {
     }
 }
 
+impl Default for Person {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Person {
     pub fn new() -> Self {
         Person {
@@ -49,6 +59,7 @@ impl Person {
             _fail_pattern: None,
             _bk_fail_pattern: None,
             _wait_for: None,
+            _token: Default::default(),
         }
     }
 
@@ -59,6 +70,7 @@ impl Person {
             _fail_pattern: None,
             _bk_fail_pattern: None,
             _wait_for: None,
+            _token: Default::default(),
         }
     }
 
@@ -71,7 +83,7 @@ impl Person {
     }
 
     pub fn set_fail_pattern(&mut self, fail_pattern: Vec<bool>) {
-        if fail_pattern.len() == 0 {
+        if fail_pattern.is_empty() {
             self._fail_pattern = None;
             self._bk_fail_pattern = None;
             return;
@@ -88,12 +100,12 @@ impl Person {
 }
 
 impl Runnable for Person {
-    fn run(&mut self) -> Result<(), Box<dyn Any>> {
+    fn run(&mut self) -> Result<(), Box<dyn Any + Send>> {
         println!("I am a person, getting my name!");
         let name = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
         // Followings are for testing only
         {
-            let error: bool = if self._fail_pattern != None {
+            let error: bool = if self._fail_pattern.is_some() {
                 if self._bk_fail_pattern.as_ref().unwrap().is_empty() {
                     self._bk_fail_pattern = self._fail_pattern.clone();
                 }
@@ -106,18 +118,22 @@ impl Runnable for Person {
             } else {
                 random()
             };
-            if self._wait_for.is_some() {
-                self._wait_for.as_ref().and_then(|v| {
-                    sleep(*v);
-                    Some(())
-                });
+            if let Some(v) = self._wait_for {
+                // waits in steps, checking for cancellation
+                let step = Duration::from_millis(10);
+                let mut waited = Duration::ZERO;
+                while waited < v {
+                    if self._token.is_cancelled() {
+                        return Err(Box::new(PersonError::Cancelled));
+                    }
+                    sleep(step.min(v - waited));
+                    waited += step;
+                }
             }
             println!("{}", error);
             if error {
                 println!("Couldn't get a name!");
-                return Err(Box::new(PersonError::as_any(
-                    &PersonError::NameFindingError,
-                )));
+                return Err(Box::new(PersonError::NameFindingError));
             }
         }
         println!("Got a name! {}", name);
@@ -129,6 +145,19 @@ impl Runnable for Person {
         let n: &Person = other.as_any().downcast_ref().unwrap();
         self.name = Some(n.name().clone());
     }
+
+    fn detach(&self) -> Option<Box<dyn Interruptable>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn attach(&mut self, detached: &Box<dyn Interruptable>) {
+        let n: &Person = detached.as_any().downcast_ref().unwrap();
+        *self = n.clone();
+    }
+
+    fn set_cancellation_token(&mut self, token: CancellationToken) {
+        self._token = token;
+    }
 }
 
 impl FallbackAble for Person {
@@ -138,6 +167,11 @@ impl FallbackAble for Person {
 }
 
 impl Interruptable for Person {
+    fn run(&mut self) -> Result<(), Box<dyn Any + Send>> {
+        Runnable::run(self)
+            .map_err(|e| -> Box<dyn Any + Send> { Box::new(PersonError::from_any(&e).clone()) })
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
@@ -0,0 +1,202 @@
+use crate::cancellation::CHECK_INTERVAL;
+use crate::failsafe_error::FailsafeError;
+use crate::policies::{Policy, PolicyData};
+use crate::run_state::PolicyActionState;
+use crate::Runnable;
+use std::any::Any;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(feature = "async")]
+use tokio::sync::Notify;
+
+/// Bulkhead, that limits the number of concurrent executions to `max_concurrency`
+///
+/// Executions over the limit are rejected with `FailsafeError::BulkheadFull`, unless
+/// `with_max_wait_time` is set, in which case they wait for a running execution to finish, up to
+/// the given time.
+///
+/// Clones of the bulkhead share the same permits.
+pub struct BulkheadPolicy {
+    policy_data: PolicyData,
+    max_concurrency: usize,
+    max_wait_time: Option<Duration>,
+    permits: Arc<Permits>,
+    // the permit held by the current execution
+    permit: Option<Permit>,
+    // when the current execution started waiting for a permit, without blocking
+    waiting_since: Option<Instant>,
+}
+
+// the permits shared by the clones of a bulkhead
+struct Permits {
+    // number of running executions
+    running: Mutex<usize>,
+    available: Condvar,
+    // wakes an async execution waiting for a permit
+    #[cfg(feature = "async")]
+    released: Arc<Notify>,
+}
+
+// releases the permit when dropped, even if the runnable panics
+struct Permit {
+    permits: Arc<Permits>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        *self.permits.running.lock().unwrap() -= 1;
+        self.permits.available.notify_one();
+        #[cfg(feature = "async")]
+        self.permits.released.notify_one();
+    }
+}
+
+// the permit belongs to the execution, a clone starts without one
+impl Clone for BulkheadPolicy {
+    fn clone(&self) -> Self {
+        BulkheadPolicy {
+            policy_data: self.policy_data.clone(),
+            max_concurrency: self.max_concurrency,
+            max_wait_time: self.max_wait_time,
+            permits: self.permits.clone(),
+            permit: None,
+            waiting_since: None,
+        }
+    }
+}
+
+impl BulkheadPolicy {
+    pub fn new(max_concurrency: usize) -> Self {
+        assert!(max_concurrency > 0, "max_concurrency must be at least 1");
+        BulkheadPolicy {
+            policy_data: Default::default(),
+            max_concurrency,
+            max_wait_time: None,
+            permits: Arc::new(Permits {
+                running: Mutex::new(0),
+                available: Condvar::new(),
+                #[cfg(feature = "async")]
+                released: Default::default(),
+            }),
+            permit: None,
+            waiting_since: None,
+        }
+    }
+
+    /// Waits up to `max_wait_time` for a permit, instead of rejecting the execution right away.
+    pub fn with_max_wait_time(mut self, max_wait_time: Duration) -> Self {
+        self.max_wait_time = Some(max_wait_time);
+        self
+    }
+
+    pub fn max_concurrency(&self) -> usize {
+        self.max_concurrency
+    }
+
+    pub fn max_wait_time(&self) -> Option<Duration> {
+        self.max_wait_time
+    }
+
+    /// Number of executions currently running through the bulkhead.
+    pub fn running(&self) -> usize {
+        *self.permits.running.lock().unwrap()
+    }
+
+    // waits up to `wait` for a permit, unless the execution is cancelled meanwhile
+    fn acquire(&self, wait: Duration) -> Result<Permit, FailsafeError> {
+        let deadline = Instant::now() + wait;
+        let mut running = self.permits.running.lock().unwrap();
+        while *running >= self.max_concurrency {
+            if self.cancellation_token().is_cancelled() {
+                return Err(FailsafeError::Cancelled);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(FailsafeError::BulkheadFull);
+            }
+            running = self
+                .permits
+                .available
+                .wait_timeout(running, remaining.min(CHECK_INTERVAL))
+                .unwrap()
+                .0;
+        }
+        *running += 1;
+        Ok(Permit {
+            permits: self.permits.clone(),
+        })
+    }
+}
+
+impl Policy for BulkheadPolicy {
+    fn policy_data(&self) -> &PolicyData {
+        &self.policy_data
+    }
+
+    fn policy_data_mut(&mut self) -> &mut PolicyData {
+        &mut self.policy_data
+    }
+
+    fn name(&self) -> String {
+        "BulkheadPolicy".to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn fork(&self) -> Box<dyn Policy> {
+        Box::new(self.clone())
+    }
+
+    fn before_run(&mut self) -> Result<(), FailsafeError> {
+        self.permit = Some(self.acquire(self.max_wait_time.unwrap_or_default())?);
+        Ok(())
+    }
+
+    fn try_before_run(&mut self) -> Result<Option<Duration>, FailsafeError> {
+        match self.acquire(Duration::ZERO) {
+            Ok(permit) => {
+                self.permit = Some(permit);
+                self.waiting_since = None;
+                return Ok(None);
+            }
+            Err(FailsafeError::BulkheadFull) => {}
+            Err(e) => {
+                self.waiting_since = None;
+                return Err(e);
+            }
+        }
+        let waiting_since = *self.waiting_since.get_or_insert_with(Instant::now);
+        let remaining = self
+            .max_wait_time
+            .unwrap_or_default()
+            .saturating_sub(waiting_since.elapsed());
+        if remaining.is_zero() {
+            self.waiting_since = None;
+            return Err(FailsafeError::BulkheadFull);
+        }
+        Ok(Some(remaining))
+    }
+
+    #[cfg(feature = "async")]
+    fn permit_released(&self) -> Option<Arc<Notify>> {
+        Some(self.permits.released.clone())
+    }
+
+    fn after_run(&mut self) {
+        self.permit = None;
+    }
+
+    fn policy_action(
+        &mut self,
+        _: &mut Box<&mut dyn Runnable>,
+        _: &FailsafeError,
+    ) -> Result<PolicyActionState, FailsafeError> {
+        Ok(PolicyActionState::Abort)
+    }
+}
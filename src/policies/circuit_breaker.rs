@@ -1,66 +1,318 @@
+use crate::events::Listeners;
 use crate::failsafe_error::FailsafeError;
 use crate::policies::{Policy, PolicyData};
 use crate::run_state::PolicyActionState;
 use crate::Runnable;
 use std::any::Any;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 use std::time::{Duration, Instant};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum CircuitBreakerState {
     Closed,
     Open,
     HalfOpen,
 }
 
+/// Circuit breaker, that temporarily rejects executions once too many of them failed
+///
+/// By default, the breaker is count based and opens after `failure_threshold` consecutive
+/// failures. `with_failure_period` makes it time based, opening once `failure_threshold`
+/// failures happened within the period, no matter how many executions succeeded in between.
+///
+/// Instead of consecutive failures, the breaker can also track a sliding window of the recent
+/// executions, either opening after a number of failures within the window (`with_failure_ratio`),
+/// or once a rate of them failed (`with_failure_rate`).
+///
+/// After `delay` the breaker is half-opened, and it's closed again after `success_threshold`
+/// successful executions. A failure while half-opened reopens it. Only `half_open_permits` trial
+/// executions, `success_threshold` by default, may be in flight at once while half-opened, the
+/// others are rejected.
+///
+/// The state can also be controlled manually, `open` and `close` force the breaker into that state
+/// until `release` returns it to automatic mode.
+///
+/// Listeners registered with `on_open`, `on_half_open` and `on_close` are called with the previous
+/// and the new state, and the metrics at the time of the transition.
+///
+/// Clones of the policy share the same breaker, so it can be kept to control or observe the
+/// breaker after it's added to a `Failsafe`.
 pub struct CircuitBreakerPolicy {
     policy_data: PolicyData,
-    circuit_breaker_state: CircuitBreakerState,
-    failure_threshold: i32,
-    success_threshold: i32,
-    delay: Duration,
-    last_attempt: Option<Instant>,
-    failure_count: i32,
-    success_count: i32,
+    breaker: Arc<Mutex<Breaker>>,
+    listeners: Listeners<CircuitBreakerState, StateListenerFn>,
+    // held while the current attempt is a trial of the half-opened breaker
+    trial: Option<TrialPermit>,
+    // the current attempt passed `before_run`, and its outcome isn't recorded yet
+    running: bool,
+}
+
+// releases the trial when dropped, even if the runnable panics or the attempt is abandoned, unless
+// the breaker changed state since
+struct TrialPermit {
+    breaker: Arc<Mutex<Breaker>>,
+    generation: u64,
+}
+
+impl Drop for TrialPermit {
+    fn drop(&mut self) {
+        let mut breaker = self.breaker.lock().unwrap_or_else(PoisonError::into_inner);
+        if breaker.generation == self.generation {
+            breaker.trials_in_flight = (breaker.trials_in_flight - 1).max(0);
+        }
+    }
+}
+
+// the trial belongs to the execution, a clone starts without one
+impl Clone for CircuitBreakerPolicy {
+    fn clone(&self) -> Self {
+        CircuitBreakerPolicy {
+            policy_data: self.policy_data.clone(),
+            breaker: self.breaker.clone(),
+            listeners: self.listeners.clone(),
+            trial: None,
+            running: false,
+        }
+    }
+}
+
+type StateListenerFn =
+    dyn FnMut(&CircuitBreakerState, &CircuitBreakerState, &CircuitBreakerMetrics) + Send;
+type StateListener = Box<StateListenerFn>;
+
+/// Snapshot of a circuit breaker's state and the executions it counts.
+///
+/// The execution counts are the ones within the sliding window when one is configured, otherwise
+/// the ones since the breaker was last closed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBreakerMetrics {
+    pub state: CircuitBreakerState,
+    pub executions: usize,
+    pub failures: usize,
+    pub successes: usize,
+    /// Failed share of the executions, between 0.0 and 1.0
+    pub failure_rate: f64,
+    pub time_in_state: Duration,
+    /// Time left until the breaker half-opens, zero unless it's open
+    pub remaining_delay: Duration,
+    pub opened: usize,
+    pub half_opened: usize,
+    pub closed: usize,
 }
 
 impl CircuitBreakerPolicy {
     pub fn new(failure_threshold: i32, delay: Duration, success_threshold: i32) -> Self {
         CircuitBreakerPolicy {
-            policy_data: PolicyData {
-                state: PolicyActionState::Success,
-                runnable_error: Box::new(()),
-                inner: None,
-            },
-            circuit_breaker_state: CircuitBreakerState::Closed,
-            failure_threshold,
-            success_threshold,
-            delay,
-            last_attempt: None,
-            failure_count: 0,
-            success_count: 0,
+            policy_data: Default::default(),
+            breaker: Arc::new(Mutex::new(Breaker {
+                circuit_breaker_state: CircuitBreakerState::Closed,
+                failure_threshold,
+                success_threshold,
+                delay,
+                last_attempt: None,
+                opened_at: None,
+                failure_count: 0,
+                success_count: 0,
+                failure_rate: None,
+                minimum_executions: 0,
+                executions: ExecutionWindow::default(),
+                stats: ExecutionStats::default(),
+                state_changed_at: Instant::now(),
+                transitions: Transitions::default(),
+                forced: false,
+                half_open_permits: success_threshold,
+                trials_in_flight: 0,
+                generation: 0,
+                pending: vec![],
+            })),
+            listeners: Default::default(),
+            trial: None,
+            running: false,
+        }
+    }
+
+    /// Only counts the failures that happened within the last `failure_period`.
+    pub fn with_failure_period(self, failure_period: Duration) -> Self {
+        self.breaker().executions.period = Some(failure_period);
+        self
+    }
+
+    /// Opens once `failures` of the last `executions` failed.
+    pub fn with_failure_ratio(self, failures: i32, executions: usize) -> Self {
+        assert!(
+            failures > 0 && failures as usize <= executions,
+            "failures must be between 1 and executions"
+        );
+        {
+            let mut breaker = self.breaker();
+            breaker.failure_threshold = failures;
+            breaker.failure_rate = None;
+            breaker.executions.capacity = Some(executions);
         }
+        self
+    }
+
+    /// Opens once `failure_rate` (between 0.0 and 1.0) of the last `executions` failed, but only
+    /// after at least `minimum_executions` were recorded.
+    pub fn with_failure_rate(
+        self,
+        failure_rate: f64,
+        minimum_executions: usize,
+        executions: usize,
+    ) -> Self {
+        assert!(
+            failure_rate > 0.0 && failure_rate <= 1.0,
+            "failure_rate must be between 0.0 and 1.0"
+        );
+        assert!(
+            minimum_executions <= executions,
+            "minimum_executions must not be greater than executions"
+        );
+        {
+            let mut breaker = self.breaker();
+            breaker.failure_rate = Some(failure_rate);
+            breaker.minimum_executions = minimum_executions;
+            breaker.executions.capacity = Some(executions);
+        }
+        self
+    }
+
+    pub fn on_open<F>(self, listener: F) -> Self
+    where
+        F: FnMut(&CircuitBreakerState, &CircuitBreakerState, &CircuitBreakerMetrics)
+            + Send
+            + 'static,
+    {
+        self.add_listener(CircuitBreakerState::Open, Box::new(listener))
+    }
+
+    pub fn on_half_open<F>(self, listener: F) -> Self
+    where
+        F: FnMut(&CircuitBreakerState, &CircuitBreakerState, &CircuitBreakerMetrics)
+            + Send
+            + 'static,
+    {
+        self.add_listener(CircuitBreakerState::HalfOpen, Box::new(listener))
+    }
+
+    pub fn on_close<F>(self, listener: F) -> Self
+    where
+        F: FnMut(&CircuitBreakerState, &CircuitBreakerState, &CircuitBreakerMetrics)
+            + Send
+            + 'static,
+    {
+        self.add_listener(CircuitBreakerState::Closed, Box::new(listener))
+    }
+
+    /// Limits the trial executions that may run at the same time while half-opened.
+    pub fn with_half_open_permits(self, half_open_permits: i32) -> Self {
+        assert!(
+            half_open_permits > 0,
+            "half_open_permits must be at least 1"
+        );
+        self.breaker().half_open_permits = half_open_permits;
+        self
+    }
+
+    /// Rejects all executions until the breaker is released.
+    pub fn open(&self) {
+        self.update(|breaker| {
+            breaker.forced = true;
+            breaker.set_open();
+        });
+    }
+
+    /// Permits all executions, regardless of their failures, until the breaker is released.
+    pub fn close(&self) {
+        self.update(|breaker| {
+            breaker.forced = true;
+            breaker.set_closed();
+        });
     }
 
-    pub fn circuit_breaker_state(&self) -> &CircuitBreakerState {
-        &self.circuit_breaker_state
+    /// Starts trial executions, and returns the breaker to automatic mode.
+    pub fn half_open(&self) {
+        self.update(|breaker| {
+            breaker.forced = false;
+            breaker.set_half_open();
+        });
+    }
+
+    /// Returns the breaker to automatic mode. When it's open, it's half-opened after `delay`.
+    pub fn release(&self) {
+        let mut breaker = self.breaker();
+        breaker.forced = false;
+        if breaker.circuit_breaker_state == CircuitBreakerState::Open {
+            breaker.opened_at = Some(Instant::now());
+        }
+    }
+
+    pub fn is_forced(&self) -> bool {
+        self.breaker().forced
+    }
+    pub fn half_open_permits(&self) -> i32 {
+        self.breaker().half_open_permits
+    }
+
+    pub fn circuit_breaker_state(&self) -> CircuitBreakerState {
+        self.breaker().circuit_breaker_state
     }
     pub fn failure_threshold(&self) -> i32 {
-        self.failure_threshold
+        self.breaker().failure_threshold
     }
     pub fn success_threshold(&self) -> i32 {
-        self.success_threshold
+        self.breaker().success_threshold
     }
     pub fn delay(&self) -> Duration {
-        self.delay
+        self.breaker().delay
+    }
+    pub fn failure_period(&self) -> Option<Duration> {
+        self.breaker().executions.period
+    }
+    pub fn failure_rate(&self) -> Option<f64> {
+        self.breaker().failure_rate
+    }
+    pub fn minimum_executions(&self) -> usize {
+        self.breaker().minimum_executions
     }
     pub fn last_attempt(&self) -> Option<Instant> {
-        self.last_attempt
+        self.breaker().last_attempt
     }
     pub fn failure_count(&self) -> i32 {
-        self.failure_count
+        self.breaker().failure_count()
     }
     pub fn success_count(&self) -> i32 {
-        self.success_count
+        self.breaker().success_count
+    }
+
+    pub fn metrics(&self) -> CircuitBreakerMetrics {
+        self.breaker().metrics()
+    }
+
+    fn breaker(&self) -> MutexGuard<'_, Breaker> {
+        self.breaker.lock().unwrap()
+    }
+
+    fn add_listener(self, state: CircuitBreakerState, listener: StateListener) -> Self {
+        self.listeners.push(state, listener);
+        self
+    }
+
+    /// Changes the breaker, and notifies the listeners of the transitions it caused once the
+    /// breaker is unlocked, so that listeners are free to use the policy. A panicking listener
+    /// leaves the breaker usable.
+    fn update<R, F: FnOnce(&mut Breaker) -> R>(&self, f: F) -> R {
+        let (result, transitions) = {
+            let mut breaker = self.breaker();
+            let result = f(&mut breaker);
+            (result, std::mem::take(&mut breaker.pending))
+        };
+        for (previous, state, metrics) in transitions {
+            self.listeners
+                .call(state, |listener| listener(&previous, &state, &metrics));
+        }
+        result
     }
 }
 
@@ -77,65 +329,356 @@ impl Policy for CircuitBreakerPolicy {
         "CircuitBreakerPolicy".to_string()
     }
 
-    fn run_guarded(&mut self, runnable: &mut Box<&mut dyn Runnable>) -> Result<(), FailsafeError> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn fork(&self) -> Box<dyn Policy> {
+        Box::new(self.clone())
+    }
+
+    fn before_run(&mut self) -> Result<(), FailsafeError> {
+        self.trial = None;
+        let generation = self.update(|breaker| breaker.acquire())?;
+        self.trial = generation.map(|generation| TrialPermit {
+            breaker: self.breaker.clone(),
+            generation,
+        });
+        self.running = true;
+        Ok(())
+    }
+
+    fn is_trial(&self) -> bool {
+        self.trial.is_some()
+    }
+
+    fn after_success(&mut self) {
+        self.update(|breaker| breaker.record_success());
+        self.trial = None;
+        self.running = false;
+        self.reset();
+    }
+
+    // an abandoned attempt counts as a failure
+    fn abandon(&mut self) {
+        if let Some(inner) = self.inner_mut().as_mut() {
+            inner.abandon();
+        }
+        if self.running {
+            self.update(|breaker| breaker.record_failure());
+        }
+        self.trial = None;
+        self.running = false;
+    }
+
+    fn policy_action(
+        &mut self,
+        _: &mut Box<&mut dyn Runnable>,
+        _: &FailsafeError,
+    ) -> Result<PolicyActionState, FailsafeError> {
+        self.update(|breaker| breaker.record_failure());
+        self.trial = None;
+        self.running = false;
+        Ok(PolicyActionState::Abort)
+    }
+}
+
+/// The breaker shared by all clones of a `CircuitBreakerPolicy`.
+struct Breaker {
+    circuit_breaker_state: CircuitBreakerState,
+    failure_threshold: i32,
+    success_threshold: i32,
+    delay: Duration,
+    last_attempt: Option<Instant>,
+    // the delay is counted from here, when the breaker opened or was released while open
+    opened_at: Option<Instant>,
+    failure_count: i32,
+    success_count: i32,
+    failure_rate: Option<f64>,
+    minimum_executions: usize,
+    executions: ExecutionWindow,
+    stats: ExecutionStats,
+    state_changed_at: Instant,
+    transitions: Transitions,
+    forced: bool,
+    half_open_permits: i32,
+    trials_in_flight: i32,
+    // changes with the state, so that trials of an earlier half-open aren't released twice
+    generation: u64,
+    // transitions the listeners haven't been notified of yet
+    pending: Vec<(
+        CircuitBreakerState,
+        CircuitBreakerState,
+        CircuitBreakerMetrics,
+    )>,
+}
+
+#[derive(Default)]
+struct ExecutionStats {
+    failures: usize,
+    successes: usize,
+}
+
+#[derive(Default)]
+struct Transitions {
+    opened: usize,
+    half_opened: usize,
+    closed: usize,
+}
+
+impl Breaker {
+    fn failure_count(&mut self) -> i32 {
+        if self.executions.is_tracked() {
+            self.executions.failures() as i32
+        } else {
+            self.failure_count
+        }
+    }
+
+    fn metrics(&mut self) -> CircuitBreakerMetrics {
+        let (executions, failures) = if self.executions.is_tracked() {
+            (self.executions.executions(), self.executions.failures())
+        } else {
+            (
+                self.stats.failures + self.stats.successes,
+                self.stats.failures,
+            )
+        };
+        let remaining_delay = match (self.circuit_breaker_state, self.opened_at) {
+            (CircuitBreakerState::Open, Some(opened_at)) => {
+                self.delay.saturating_sub(opened_at.elapsed())
+            }
+            _ => Duration::ZERO,
+        };
+        CircuitBreakerMetrics {
+            state: self.circuit_breaker_state,
+            executions,
+            failures,
+            successes: executions - failures,
+            failure_rate: if executions == 0 {
+                0.0
+            } else {
+                failures as f64 / executions as f64
+            },
+            time_in_state: self.state_changed_at.elapsed(),
+            remaining_delay,
+            opened: self.transitions.opened,
+            half_opened: self.transitions.half_opened,
+            closed: self.transitions.closed,
+        }
+    }
+
+    // returns the generation when the attempt is a trial of the half-opened breaker
+    fn acquire(&mut self) -> Result<Option<u64>, FailsafeError> {
         if self.circuit_breaker_state == CircuitBreakerState::Open {
-            let now = Instant::now();
-            if let Some(last_attempt) = self.last_attempt {
-                if now - last_attempt > self.delay {
-                    self.circuit_breaker_state = CircuitBreakerState::HalfOpen;
-                } else {
-                    return Err(FailsafeError::CircuitBreakerOpen);
+            match self.opened_at {
+                Some(opened_at) if !self.forced && opened_at.elapsed() > self.delay => {
+                    self.set_half_open()
                 }
-            } else {
+                _ => return Err(FailsafeError::CircuitBreakerOpen),
+            }
+        }
+        if self.circuit_breaker_state == CircuitBreakerState::HalfOpen {
+            if self.trials_in_flight >= self.half_open_permits {
                 return Err(FailsafeError::CircuitBreakerOpen);
             }
+            self.trials_in_flight += 1;
         }
         self.last_attempt = Some(Instant::now());
-        match runnable.run() {
-            Ok(_) => match self.circuit_breaker_state {
-                CircuitBreakerState::Closed => {
-                    self.reset();
-                    Ok(())
-                }
-                CircuitBreakerState::HalfOpen => {
-                    self.success_count += 1;
-                    if self.success_count >= self.success_threshold {
-                        self.reset();
-                    }
-                    Ok(())
+        Ok(
+            (self.circuit_breaker_state == CircuitBreakerState::HalfOpen)
+                .then_some(self.generation),
+        )
+    }
+
+    fn record_success(&mut self) {
+        self.executions.record(true);
+        self.stats.successes += 1;
+        if self.forced {
+            return;
+        }
+        match self.circuit_breaker_state {
+            CircuitBreakerState::HalfOpen => {
+                self.success_count += 1;
+                if self.success_count >= self.success_threshold {
+                    self.set_closed();
                 }
-                CircuitBreakerState::Open => Err(FailsafeError::CircuitBreakerOpen),
-            },
-            Err(e) => {
-                self.policy_data_mut().state = PolicyActionState::CircuitBreakerError;
-                Err(FailsafeError::RunnableError(e))
             }
+            _ => self.failure_count = 0,
         }
     }
 
-    fn policy_action(
-        &mut self,
-        _: &mut Box<&mut dyn Runnable>,
-    ) -> Result<PolicyActionState, FailsafeError> {
-        match self.policy_data().state {
-            PolicyActionState::CircuitBreakerError => {
-                self.failure_count += 1;
-                if self.failure_count >= self.failure_threshold {
-                    self.circuit_breaker_state = CircuitBreakerState::Open
+    fn record_failure(&mut self) {
+        self.executions.record(false);
+        self.stats.failures += 1;
+        if self.forced {
+            return;
+        }
+        self.failure_count += 1;
+        match self.circuit_breaker_state {
+            CircuitBreakerState::HalfOpen => self.set_open(),
+            _ => {
+                if self.failure_threshold_exceeded() {
+                    self.set_open()
                 }
-                Err(FailsafeError::CircuitBreakerOpen)
             }
-            _ => Ok(PolicyActionState::Success),
         }
     }
 
-    fn reset(&mut self) {
+    fn failure_threshold_exceeded(&mut self) -> bool {
+        match self.failure_rate {
+            Some(failure_rate) => {
+                let executions = self.executions.executions();
+                executions > 0
+                    && executions >= self.minimum_executions
+                    && self.executions.failures() as f64 / executions as f64 >= failure_rate
+            }
+            None => self.failure_count() >= self.failure_threshold,
+        }
+    }
+
+    fn set_open(&mut self) {
+        self.transition_to(CircuitBreakerState::Open);
+    }
+
+    fn set_half_open(&mut self) {
+        self.success_count = 0;
+        self.transition_to(CircuitBreakerState::HalfOpen);
+    }
+
+    fn set_closed(&mut self) {
+        self.transition_to(CircuitBreakerState::Closed);
         self.last_attempt = None;
         self.failure_count = 0;
         self.success_count = 0;
-        self.circuit_breaker_state = CircuitBreakerState::Closed;
-        self.inner_mut()
-            .as_mut()
-            .and_then(|inner| Some(inner.reset()));
+        self.executions.clear();
+        self.stats = ExecutionStats::default();
+    }
+
+    fn transition_to(&mut self, state: CircuitBreakerState) {
+        if self.circuit_breaker_state == state {
+            return;
+        }
+        match state {
+            CircuitBreakerState::Open => self.transitions.opened += 1,
+            CircuitBreakerState::HalfOpen => self.transitions.half_opened += 1,
+            CircuitBreakerState::Closed => self.transitions.closed += 1,
+        }
+        let previous = self.circuit_breaker_state;
+        self.circuit_breaker_state = state;
+        self.trials_in_flight = 0;
+        self.generation += 1;
+        self.state_changed_at = Instant::now();
+        if state == CircuitBreakerState::Open {
+            self.opened_at = Some(self.state_changed_at);
+        }
+        let metrics = self.metrics();
+        self.pending.push((previous, state, metrics));
+    }
+}
+
+/// Outcomes of the recent executions, limited to the last `capacity` ones and to the ones within
+/// `period` when they are set.
+///
+/// With a capacity the outcomes themselves are kept, at most `capacity` of them. With only a period
+/// they are counted in `BUCKETS` slices of it, so an outcome leaves the window up to a slice early.
+/// Either way the counts are kept up to date as outcomes come and go.
+#[derive(Default)]
+struct ExecutionWindow {
+    period: Option<Duration>,
+    capacity: Option<usize>,
+    outcomes: VecDeque<(Instant, bool)>,
+    buckets: VecDeque<Bucket>,
+    executions: usize,
+    failures: usize,
+}
+
+const BUCKETS: u32 = 10;
+
+struct Bucket {
+    started: Instant,
+    executions: usize,
+    failures: usize,
+}
+
+impl ExecutionWindow {
+    fn is_tracked(&self) -> bool {
+        self.period.is_some() || self.capacity.is_some()
+    }
+
+    fn record(&mut self, success: bool) {
+        if !self.is_tracked() {
+            return;
+        }
+        let now = Instant::now();
+        match (self.capacity, self.period) {
+            (None, Some(period)) => match self.buckets.back_mut() {
+                Some(bucket) if now - bucket.started < period / BUCKETS => {
+                    bucket.executions += 1;
+                    bucket.failures += usize::from(!success);
+                }
+                _ => self.buckets.push_back(Bucket {
+                    started: now,
+                    executions: 1,
+                    failures: usize::from(!success),
+                }),
+            },
+            _ => self.outcomes.push_back((now, success)),
+        }
+        self.executions += 1;
+        self.failures += usize::from(!success);
+        self.prune();
+    }
+
+    fn prune(&mut self) {
+        if let Some(capacity) = self.capacity {
+            while self.outcomes.len() > capacity {
+                self.pop_outcome();
+            }
+        }
+        if let Some(period) = self.period {
+            while let Some((at, _)) = self.outcomes.front() {
+                if at.elapsed() <= period {
+                    break;
+                }
+                self.pop_outcome();
+            }
+            while let Some(bucket) = self.buckets.front() {
+                if bucket.started.elapsed() <= period {
+                    break;
+                }
+                self.executions -= bucket.executions;
+                self.failures -= bucket.failures;
+                self.buckets.pop_front();
+            }
+        }
+    }
+
+    fn pop_outcome(&mut self) {
+        if let Some((_, success)) = self.outcomes.pop_front() {
+            self.executions -= 1;
+            self.failures -= usize::from(!success);
+        }
+    }
+
+    fn executions(&mut self) -> usize {
+        self.prune();
+        self.executions
+    }
+
+    fn failures(&mut self) -> usize {
+        self.prune();
+        self.failures
+    }
+
+    fn clear(&mut self) {
+        self.outcomes.clear();
+        self.buckets.clear();
+        self.executions = 0;
+        self.failures = 0;
     }
 }
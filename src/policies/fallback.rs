@@ -1,9 +1,12 @@
+use crate::context::ExecutionContext;
+use crate::events::{EventKind, ExecutionEvent};
+use crate::failsafe::erase;
 use crate::failsafe_error::FailsafeError;
 use crate::policies::{Policy, PolicyData};
 use crate::run_state::PolicyActionState;
 use crate::Runnable;
 use std::any::Any;
-use std::fmt::Display;
+use std::sync::{Arc, Mutex, PoisonError};
 
 #[macro_export]
 macro_rules! on_fallback {
@@ -12,18 +15,110 @@ macro_rules! on_fallback {
     };
 }
 
+type FallbackFn = Box<dyn FnMut() -> Box<dyn FallbackAble> + Send>;
+// fallbacks get the error that caused them, and may fail with a runnable error of their own
+type UpdateFn = Box<
+    dyn FnMut(
+            &FailsafeError,
+            &ExecutionContext,
+        ) -> Result<Box<dyn FallbackAble>, Box<dyn Any + Send>>
+        + Send,
+>;
+type ValueFn = Box<
+    dyn FnMut(&FailsafeError, &ExecutionContext) -> Result<Box<dyn Any>, Box<dyn Any + Send>>
+        + Send,
+>;
+
+enum Fallback {
+    // updates the runnable, and fails with `UsedFallback`
+    Update(UpdateFn),
+    // the value returned in place of the runnable's
+    Value(ValueFn),
+}
+
+/// Fallback policy, that handles failures with a fallback
+///
+/// `new` takes a fallback that updates the runnable, after which the execution fails with
+/// `FailsafeError::UsedFallback`, to be handled by the outer policies. `of_value` takes a fallback
+/// value, that `Failsafe::run` and `Failsafe::get` return instead.
+///
+/// `of_update` and `of_result` do the same with fallbacks that get the error being handled and the
+/// context of the attempt, e.g. to fall back differently on timeouts and open circuits. Their
+/// errors fail the execution with `FailsafeError::RunnableError` when they're of the runnable's
+/// error type, and with `FailsafeError::FallbackError` otherwise.
+///
+/// Executions sharing the policy call the fallback one at a time, so it shouldn't run the
+/// `Failsafe` it belongs to. A panicking fallback leaves the policy usable.
+// clones share the fallback function, along with whatever it captured
+#[derive(Clone)]
 pub struct FallbackPolicy {
-    fallback: Box<dyn FnMut() -> Box<dyn FallbackAble>>,
+    fallback: Arc<Mutex<Fallback>>,
     policy_data: PolicyData,
+    // the runnable was updated with the fallback in this execution
+    used: bool,
 }
 
 impl FallbackPolicy {
-    pub(crate) fn new(fallback: Box<dyn FnMut() -> Box<dyn FallbackAble>>) -> Self {
+    pub fn new(mut fallback: FallbackFn) -> Self {
+        Self::with(Fallback::Update(Box::new(move |_, _| Ok(fallback()))))
+    }
+
+    /// Falls back to the value returned by `fallback`, it should be of the runnable's value type.
+    pub fn of_value<T, F>(mut fallback: F) -> Self
+    where
+        T: 'static,
+        F: FnMut() -> T + Send + 'static,
+    {
+        Self::with(Fallback::Value(Box::new(move |_, _| {
+            Ok(Box::new(fallback()))
+        })))
+    }
+
+    /// Updates the runnable with the fallback `fallback` returns for the error, or fails with the
+    /// error `fallback` returns.
+    pub fn of_update<E, F>(mut fallback: F) -> Self
+    where
+        E: Send + 'static,
+        F: FnMut(&FailsafeError, &ExecutionContext) -> Result<Box<dyn FallbackAble>, E>
+            + Send
+            + 'static,
+    {
+        Self::with(Fallback::Update(Box::new(move |error, context| {
+            fallback(error, context).map_err(erase)
+        })))
+    }
+
+    /// Falls back to the value `fallback` returns for the error, or fails with the error it
+    /// returns.
+    pub fn of_result<T, E, F>(mut fallback: F) -> Self
+    where
+        T: 'static,
+        E: Send + 'static,
+        F: FnMut(&FailsafeError, &ExecutionContext) -> Result<T, E> + Send + 'static,
+    {
+        Self::with(Fallback::Value(Box::new(
+            move |error, context| match fallback(error, context) {
+                Ok(value) => Ok(Box::new(value)),
+                Err(e) => Err(erase(e)),
+            },
+        )))
+    }
+
+    fn with(fallback: Fallback) -> Self {
         FallbackPolicy {
-            fallback,
+            fallback: Arc::new(Mutex::new(fallback)),
             policy_data: PolicyData::default(),
+            used: false,
         }
     }
+
+    /// Calls `listener` before falling back, with the error that caused it.
+    pub fn on_fallback<F: FnMut(&ExecutionEvent) + Send + 'static>(self, listener: F) -> Self {
+        self.policy_data
+            .listeners
+            .push(EventKind::Fallback, Box::new(listener));
+        self
+    }
 }
 
 impl Policy for FallbackPolicy {
@@ -39,12 +134,55 @@ impl Policy for FallbackPolicy {
         "FallbackPolicy".to_string()
     }
 
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn fork(&self) -> Box<dyn Policy> {
+        Box::new(FallbackPolicy {
+            used: false,
+            ..self.clone()
+        })
+    }
+
+    fn used_fallback(&self) -> bool {
+        self.used
+    }
+
     fn policy_action(
         &mut self,
         runnable: &mut Box<&mut dyn Runnable>,
+        error: &FailsafeError,
     ) -> Result<PolicyActionState, FailsafeError> {
-        runnable.update(&(self.fallback)());
-        Ok(PolicyActionState::UsingFallback)
+        self.emit(EventKind::Fallback, Some(error));
+        let context = ExecutionContext {
+            last_error: Some(error),
+            ..self.attempt_context(&[])
+        };
+        // the runnable is updated once the fallback is unlocked
+        let mut guard = self.fallback.lock().unwrap_or_else(PoisonError::into_inner);
+        match &mut *guard {
+            Fallback::Update(fallback) => {
+                let update = fallback(error, &context).map_err(FailsafeError::RunnableError)?;
+                drop(guard);
+                runnable.update(&update);
+                self.used = true;
+                Ok(PolicyActionState::UsingFallback)
+            }
+            Fallback::Value(fallback) => {
+                let value = fallback(error, &context).map_err(FailsafeError::RunnableError)?;
+                drop(guard);
+                if runnable.set_fallback_value(value) {
+                    Ok(PolicyActionState::Success)
+                } else {
+                    Ok(PolicyActionState::UsingFallback)
+                }
+            }
+        }
     }
 }
 
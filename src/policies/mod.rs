@@ -1,9 +1,14 @@
+use crate::cancellation::CancellationToken;
+use crate::context::ExecutionContext;
+use crate::events::{EventKind, EventListeners, ExecutionEvent};
 use crate::failsafe_error::FailsafeError;
+use crate::report::PolicyError;
 use crate::run_state::PolicyActionState;
 use crate::Runnable;
 use std::any::Any;
-use std::error::Error;
+use std::time::{Duration, Instant};
 
+pub mod bulkhead;
 pub mod circuit_breaker;
 pub mod fallback;
 pub mod rate_limiter;
@@ -12,21 +17,53 @@ pub mod timeout;
 
 pub struct PolicyData {
     state: PolicyActionState,
-    runnable_error: Box<dyn Any>,
+    cancellation_token: CancellationToken,
+    listeners: EventListeners,
+    // attempts of the current execution, and when the first one started
+    attempts: u32,
+    started: Option<Instant>,
+    outer: Outer,
     inner: Option<Box<dyn Policy>>,
 }
 
+// what the outer policies know about the current attempt, passed down to the runnable
+#[derive(Clone, Copy, Default)]
+struct Outer {
+    started: Option<Instant>,
+    trial: bool,
+    fallback: bool,
+}
+
+// a clone starts a new execution, with forks of the inner policies
+impl Clone for PolicyData {
+    fn clone(&self) -> Self {
+        PolicyData {
+            state: self.state.clone(),
+            cancellation_token: self.cancellation_token.clone(),
+            listeners: self.listeners.clone(),
+            attempts: 0,
+            started: None,
+            outer: Default::default(),
+            inner: self.inner.as_ref().map(|inner| inner.fork()),
+        }
+    }
+}
+
 impl Default for PolicyData {
     fn default() -> Self {
         PolicyData {
             state: PolicyActionState::Success,
-            runnable_error: Box::new(()),
+            cancellation_token: Default::default(),
+            listeners: Default::default(),
+            attempts: 0,
+            started: None,
+            outer: Default::default(),
             inner: None,
         }
     }
 }
 
-pub trait Policy {
+pub trait Policy: Send + Sync {
     fn policy_data(&self) -> &PolicyData;
     fn policy_data_mut(&mut self) -> &mut PolicyData;
 
@@ -50,44 +87,139 @@ pub trait Policy {
         self.policy_data_mut().state = state;
     }
 
-    fn runnable_error(&self) -> &Box<dyn Any> {
-        &self.policy_data().runnable_error
+    fn cancellation_token(&self) -> &CancellationToken {
+        &self.policy_data().cancellation_token
     }
 
-    fn set_runnable_error(&mut self, err: Box<dyn Any>) {
-        self.policy_data_mut().runnable_error = err;
+    // sets the token of the execution, for this policy and its inner policies
+    fn set_cancellation_token(&mut self, token: CancellationToken) {
+        if let Some(inner) = self.inner_mut().as_mut() {
+            inner.set_cancellation_token(token.clone());
+        }
+        self.policy_data_mut().cancellation_token = token;
     }
 
     fn name(&self) -> String;
 
+    fn as_any(&self) -> &dyn Any;
+
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Creates a copy of the policy, and its inner policies, for a new execution. The state that
+    /// outlives executions, like circuit breaker counts, is shared with the copy.
+    fn fork(&self) -> Box<dyn Policy>;
+
+    /// Calls `listener` when the policy succeeds.
+    fn on_success<F>(self, listener: F) -> Self
+    where
+        Self: Sized,
+        F: FnMut(&ExecutionEvent) + Send + 'static,
+    {
+        self.policy_data()
+            .listeners
+            .push(EventKind::Success, Box::new(listener));
+        self
+    }
+
+    /// Calls `listener` when the policy fails, with the error it fails with.
+    fn on_failure<F>(self, listener: F) -> Self
+    where
+        Self: Sized,
+        F: FnMut(&ExecutionEvent) + Send + 'static,
+    {
+        self.policy_data()
+            .listeners
+            .push(EventKind::Failure, Box::new(listener));
+        self
+    }
+
+    // notifies the listeners of the policy
+    fn emit(&self, kind: EventKind, error: Option<&FailsafeError>) {
+        let data = self.policy_data();
+        data.listeners.emit(kind, || ExecutionEvent {
+            policy: self.name(),
+            attempt: data.attempts,
+            elapsed: data.started.map(|s| s.elapsed()).unwrap_or_default(),
+            error,
+        });
+    }
+
+    // notifies the listeners of the outcome of the policy
+    fn emit_outcome(&self, result: &Result<(), FailsafeError>) {
+        match result {
+            Ok(_) => self.emit(EventKind::Success, None),
+            Err(e) => self.emit(EventKind::Failure, Some(e)),
+        }
+    }
+
+    // counts a new attempt of the policy
+    fn start_attempt(&mut self) {
+        let data = self.policy_data_mut();
+        data.attempts += 1;
+        data.started.get_or_insert_with(Instant::now);
+    }
+
+    fn attempts(&self) -> u32 {
+        self.policy_data().attempts
+    }
+
     fn run(
         &mut self,
-        mut runnable: &mut Box<&mut dyn Runnable>,
-        policy_errors: &mut Vec<FailsafeError>,
+        runnable: &mut Box<&mut dyn Runnable>,
+        policy_errors: &mut Vec<PolicyError>,
+    ) -> Result<(), FailsafeError> {
+        let result = self.run_attempts(runnable, policy_errors);
+        self.emit_outcome(&result);
+        result
+    }
+
+    fn run_attempts(
+        &mut self,
+        runnable: &mut Box<&mut dyn Runnable>,
+        policy_errors: &mut Vec<PolicyError>,
     ) -> Result<(), FailsafeError> {
         loop {
-            self.before_run();
-            let e = if self.inner_mut().is_some() {
+            if self.cancellation_token().is_cancelled() {
+                return Err(FailsafeError::Cancelled);
+            }
+            self.start_attempt();
+            self.before_run()?;
+            // the execution may be cancelled while waiting for a permit
+            if self.cancellation_token().is_cancelled() {
+                self.after_run();
+                return Err(FailsafeError::Cancelled);
+            }
+            let result = if self.inner_mut().is_some() {
+                self.prepare_inner();
                 let result = self
                     .inner_mut()
                     .as_mut()
-                    .and_then(|inner| Some(inner.run(runnable, policy_errors)))
+                    .map(|inner| inner.run(runnable, policy_errors))
                     .unwrap();
-                match result {
-                    Ok(_) => {
-                        self.reset();
-                        return Ok(());
-                    }
-                    Err(e) => e,
+                let result = self.inner_finished(result);
+                if result.is_ok() {
+                    self.after_success();
                 }
+                result
             } else {
-                match self.run_guarded(runnable) {
-                    Ok(_) => return Ok(()),
-                    Err(e) => e,
-                }
+                runnable.set_cancellation_token(self.attempt_token());
+                let context = self.attempt_context(policy_errors);
+                self.run_guarded(runnable, &context)
+            };
+            self.after_run();
+            let e = match result {
+                Ok(_) => return Ok(()),
+                Err(e) => e,
             };
-            policy_errors.push(e);
-            let result = self.policy_action(&mut runnable);
+            let result = self.policy_action(runnable, &e);
+            if let Ok(PolicyActionState::Abort) = result {
+                return Err(e);
+            }
+            policy_errors.push(PolicyError {
+                policy: self.name(),
+                attempt: self.attempts(),
+                error: e,
+            });
             if result.is_err() {
                 return Err(result.err().unwrap());
             }
@@ -96,22 +228,125 @@ pub trait Policy {
                     self.reset();
                     Ok(())
                 }
-                Ok(PolicyActionState::Retry) => continue,
+                Ok(PolicyActionState::Retry) => {
+                    if let Some(delay) = self.take_retry_delay() {
+                        self.cancellation_token().sleep(delay)?;
+                    }
+                    continue;
+                }
                 Ok(PolicyActionState::UsingFallback) => Err(FailsafeError::UsedFallback),
                 _ => Ok(()),
             };
         }
     }
 
-    // does nothing for now.
-    fn before_run(&self) {}
+    // whether the current attempt is a trial, e.g. of a half-open circuit breaker
+    fn is_trial(&self) -> bool {
+        false
+    }
+
+    // whether the policy fell back during this execution, so that the runnable runs with the
+    // fallback from then on
+    fn used_fallback(&self) -> bool {
+        false
+    }
+
+    // the context of the current attempt, as known to this policy and the outer ones, with the
+    // errors handled so far
+    fn attempt_context<'a>(&self, policy_errors: &'a [PolicyError]) -> ExecutionContext<'a> {
+        let data = self.policy_data();
+        ExecutionContext {
+            attempt: data.attempts,
+            started: data
+                .outer
+                .started
+                .or(data.started)
+                .unwrap_or_else(Instant::now),
+            last_error: policy_errors.last().map(|e| &e.error),
+            fallback: data.outer.fallback || self.used_fallback(),
+            trial: data.outer.trial || self.is_trial(),
+            cancellation_token: self.attempt_token(),
+        }
+    }
+
+    // hands what's known about the current attempt down to the inner policy, before running it
+    fn prepare_inner(&mut self) {
+        let context = self.attempt_context(&[]);
+        let outer = Outer {
+            started: Some(context.started),
+            trial: context.trial,
+            fallback: context.fallback,
+        };
+        if let Some(inner) = self.inner_mut().as_mut() {
+            inner.policy_data_mut().outer = outer;
+        }
+    }
+
+    // called before every attempt of this policy, an error rejects the attempt
+    fn before_run(&mut self) -> Result<(), FailsafeError> {
+        Ok(())
+    }
+
+    // like `before_run`, without blocking: returns the time to wait before trying again instead,
+    // used by async executions
+    fn try_before_run(&mut self) -> Result<Option<Duration>, FailsafeError> {
+        self.before_run().map(|_| None)
+    }
+
+    // notified when `try_before_run` may pass before the time it returned, async executions stop
+    // waiting then
+    #[cfg(feature = "async")]
+    fn permit_released(&self) -> Option<std::sync::Arc<tokio::sync::Notify>> {
+        None
+    }
+
+    // the token of the current attempt, given to the runnable or the inner policies
+    fn attempt_token(&self) -> CancellationToken {
+        self.cancellation_token().clone()
+    }
+
+    // the time left for the current attempt, async executions stop waiting for it afterwards
+    fn attempt_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    // the delay to wait after `PolicyActionState::Retry`, before the next attempt
+    fn take_retry_delay(&mut self) -> Option<Duration> {
+        None
+    }
+
+    // checks the result of the inner policies, before the policy handles it
+    fn inner_finished(&mut self, result: Result<(), FailsafeError>) -> Result<(), FailsafeError> {
+        result
+    }
+
+    // called after every attempt of this policy that passed `before_run`, whatever its outcome
+    fn after_run(&mut self) {}
+
+    // called when the attempt was dropped before the policy saw its outcome, e.g. by an async
+    // timeout, so that the policy and its inner policies release what the attempt holds
+    fn abandon(&mut self) {
+        if let Some(inner) = self.inner_mut().as_mut() {
+            inner.abandon();
+        }
+        self.after_run();
+    }
+
+    // called after every successful attempt of this policy
+    fn after_success(&mut self) {
+        self.reset();
+    }
 
-    fn run_guarded(&mut self, runnable: &mut Box<&mut dyn Runnable>) -> Result<(), FailsafeError> {
-        let result = runnable.run();
+    fn run_guarded(
+        &mut self,
+        runnable: &mut Box<&mut dyn Runnable>,
+        context: &ExecutionContext,
+    ) -> Result<(), FailsafeError> {
+        let result = runnable.run_with_context(context);
         match result {
             Ok(_) => {
-                self.reset();
-                return Ok(());
+                self.after_success();
+                Ok(())
             }
             Err(e) => Err(FailsafeError::RunnableError(e)),
         }
@@ -120,11 +355,12 @@ pub trait Policy {
     fn policy_action(
         &mut self,
         runnable: &mut Box<&mut dyn Runnable>,
+        error: &FailsafeError,
     ) -> Result<PolicyActionState, FailsafeError>;
 
     fn reset(&mut self) {
-        self.inner_mut()
-            .as_mut()
-            .and_then(|inner| Some(inner.reset()));
+        if let Some(inner) = self.inner_mut().as_mut() {
+            inner.reset();
+        }
     }
 }
@@ -2,32 +2,68 @@ use crate::failsafe_error::FailsafeError;
 use crate::policies::{Policy, PolicyData};
 use crate::run_state::PolicyActionState;
 use crate::Runnable;
-use std::time::Duration;
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum LimiterType {
     Smooth,
     Burst,
 }
 
+/// Rate limiter, that limits executions to `max_execution` per `duration`
+///
+/// A smooth limiter spaces the executions evenly, permitting one every `duration / max_execution`.
+/// A bursty limiter grants `max_execution` permits at the start of every `duration` period, which
+/// can be used all at once.
+/// Executions arriving before their permit is available are rejected with
+/// `FailsafeError::RateLimitExceeded`, unless `with_max_wait_time` is set, in which case they block
+/// until a permit is available or the wait time runs out.
+///
+/// Clones of the limiter share the same permits.
+#[derive(Clone)]
 pub struct RateLimiter {
     policy_data: PolicyData,
     limiter_type: LimiterType,
     max_execution: i32,
     duration: Duration,
-    can_run: bool,
+    max_wait_time: Option<Duration>,
+    permits: Arc<Mutex<Permits>>,
+    // when the current execution started waiting for a permit, without blocking
+    waiting_since: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Permits {
+    // when the next smooth permit is available
+    next_permit: Option<Instant>,
+    // start of the current bursty period, and the permits used in it
+    period_start: Option<Instant>,
+    used: i32,
 }
 
 impl RateLimiter {
     pub fn new(limiter_type: LimiterType, max_execution: i32, duration: Duration) -> Self {
+        assert!(max_execution > 0, "max_execution must be at least 1");
         RateLimiter {
             policy_data: Default::default(),
             limiter_type,
             max_execution,
             duration,
-            can_run: false,
+            max_wait_time: None,
+            permits: Default::default(),
+            waiting_since: None,
         }
     }
 
+    /// Blocks the execution for up to `max_wait_time` waiting for a permit, instead of rejecting
+    /// it right away.
+    pub fn with_max_wait_time(mut self, max_wait_time: Duration) -> Self {
+        self.max_wait_time = Some(max_wait_time);
+        self
+    }
+
     pub fn limiter_type(&self) -> &LimiterType {
         &self.limiter_type
     }
@@ -37,6 +73,62 @@ impl RateLimiter {
     pub fn duration(&self) -> Duration {
         self.duration
     }
+    pub fn max_wait_time(&self) -> Option<Duration> {
+        self.max_wait_time
+    }
+
+    /// Time between two permits of a smooth limiter.
+    pub fn interval(&self) -> Duration {
+        self.duration / self.max_execution as u32
+    }
+
+    fn acquire(&self) -> Result<(), FailsafeError> {
+        let deadline = Instant::now() + self.max_wait_time.unwrap_or_default();
+        loop {
+            let wait = match self.try_acquire() {
+                Ok(()) => return Ok(()),
+                Err(wait) => wait,
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if wait > remaining {
+                return Err(FailsafeError::RateLimitExceeded);
+            }
+            // another execution may take the permit meanwhile, in which case we wait again
+            self.cancellation_token().sleep(wait)?;
+        }
+    }
+
+    /// Takes a permit if one is available, otherwise returns the time until the next one.
+    fn try_acquire(&self) -> Result<(), Duration> {
+        let mut permits = self.permits.lock().unwrap();
+        let now = Instant::now();
+        match self.limiter_type {
+            LimiterType::Smooth => match permits.next_permit {
+                Some(next_permit) if next_permit > now => Err(next_permit - now),
+                _ => {
+                    permits.next_permit = Some(now + self.interval());
+                    Ok(())
+                }
+            },
+            LimiterType::Burst => {
+                let period_start = *permits.period_start.get_or_insert(now);
+                let elapsed = now.duration_since(period_start);
+                if elapsed >= self.duration {
+                    // move to the start of the current period, skipping the idle ones
+                    let into_period = elapsed.as_nanos() % self.duration.as_nanos().max(1);
+                    permits.period_start = Some(now - Duration::from_nanos(into_period as u64));
+                    permits.used = 0;
+                }
+                if permits.used < self.max_execution {
+                    permits.used += 1;
+                    Ok(())
+                } else {
+                    let period_end = permits.period_start.unwrap() + self.duration;
+                    Err(period_end.saturating_duration_since(now))
+                }
+            }
+        }
+    }
 }
 
 impl Policy for RateLimiter {
@@ -52,10 +144,51 @@ impl Policy for RateLimiter {
         "RateLimiter".to_string()
     }
 
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn fork(&self) -> Box<dyn Policy> {
+        Box::new(self.clone())
+    }
+
+    fn before_run(&mut self) -> Result<(), FailsafeError> {
+        self.acquire()
+    }
+
+    fn try_before_run(&mut self) -> Result<Option<Duration>, FailsafeError> {
+        let wait = match self.try_acquire() {
+            Ok(()) => {
+                self.waiting_since = None;
+                return Ok(None);
+            }
+            Err(wait) => wait,
+        };
+        if self.cancellation_token().is_cancelled() {
+            self.waiting_since = None;
+            return Err(FailsafeError::Cancelled);
+        }
+        let waiting_since = *self.waiting_since.get_or_insert_with(Instant::now);
+        let remaining = self
+            .max_wait_time
+            .unwrap_or_default()
+            .saturating_sub(waiting_since.elapsed());
+        if wait > remaining {
+            self.waiting_since = None;
+            return Err(FailsafeError::RateLimitExceeded);
+        }
+        Ok(Some(wait))
+    }
+
     fn policy_action(
         &mut self,
-        runnable: &mut Box<&mut dyn Runnable>,
+        _: &mut Box<&mut dyn Runnable>,
+        _: &FailsafeError,
     ) -> Result<PolicyActionState, FailsafeError> {
-        todo!()
+        Ok(PolicyActionState::Abort)
     }
 }
@@ -1,41 +1,261 @@
+use crate::events::{EventKind, ExecutionEvent};
 use crate::failsafe_error::FailsafeError;
 use crate::policies::{Policy, PolicyData};
 use crate::run_state::PolicyActionState;
 use crate::Runnable;
+use rand::Rng;
 use std::any::Any;
-use std::thread::sleep;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 /// Retry policy, that retries given amount time with a delay before failing
 ///
 /// This policy will retry execution pipeline with given delay between attempts, if execution fails
 /// after retries have been exceeded, it will return `FailsafeError::Runnable<Box<Any>`
 ///
+/// `unlimited` retries without a limit, which is usually combined with `with_max_duration` to keep
+/// retrying until the time budget is spent.
+///
+/// By default every failure is retried. Once `handle` or `handle_if` is used, only the runnable
+/// errors matching one of them are retried, and errors matching `abort_on` are never retried. Errors
+/// that are not retried are returned as they are.
+///
 /// ## Features
 ///
 /// - [x] Retries
 /// - [x] Delay between retries
-/// - [ ] Back off [Link](https://failsafe.dev/javadoc/core/dev/failsafe/RetryPolicyBuilder.html#withBackoff-long-long-java.time.temporal.ChronoUnit-)
-/// - [ ] Random delay
-/// - [ ] Jitter [Check](https://failsafe.dev/javadoc/core/dev/failsafe/RetryPolicyBuilder.html#withJitter-double-)
-/// - [ ] No limit
+/// - [x] Back off [Link](https://failsafe.dev/javadoc/core/dev/failsafe/RetryPolicyBuilder.html#withBackoff-long-long-java.time.temporal.ChronoUnit-)
+/// - [x] Random delay
+/// - [x] Jitter [Check](https://failsafe.dev/javadoc/core/dev/failsafe/RetryPolicyBuilder.html#withJitter-double-)
+/// - [x] No limit
+/// - [x] Max duration
+/// - [x] Handled and aborting errors
 ///
+#[derive(Clone)]
 pub struct RetryPolicy {
     policy_data: PolicyData,
-    retries: i32,
+    // no limit when `None`
+    retries: Option<i32>,
     delay: Duration,
+    max_delay: Option<Duration>,
+    delay_factor: f64,
+    random_delay: Option<(Duration, Duration)>,
+    jitter: Option<Jitter>,
+    max_duration: Option<Duration>,
+    handlers: Vec<ErrorPredicate>,
+    abort_conditions: Vec<ErrorPredicate>,
     tries: i32,
+    started: Option<Instant>,
+    // the delay before the next attempt, waited by the run loop
+    next_delay: Option<Duration>,
+}
+
+type ErrorPredicate = Arc<dyn Fn(&dyn Any) -> bool + Send + Sync>;
+
+/// Randomly added to or subtracted from the computed delay, so that clients failing at the same
+/// time don't retry in lockstep.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Jitter {
+    /// Fraction of the delay, between 0.0 and 1.0
+    Factor(f64),
+    /// Fixed amount of time
+    Duration(Duration),
 }
 
 impl RetryPolicy {
-    pub(crate) fn new(retries: i32, delay: Duration) -> Self {
-        let policy = RetryPolicy {
+    pub fn new(retries: i32, delay: Duration) -> Self {
+        assert!(retries >= 0, "retries must not be negative");
+        Self::with_retries(Some(retries), delay)
+    }
+
+    /// Retries until the runnable succeeds, or the execution is stopped otherwise, e.g. by
+    /// `with_max_duration`, a timeout or a cancellation.
+    pub fn unlimited(delay: Duration) -> Self {
+        Self::with_retries(None, delay)
+    }
+
+    fn with_retries(retries: Option<i32>, delay: Duration) -> Self {
+        RetryPolicy {
             policy_data: Default::default(),
             retries,
             delay,
+            max_delay: None,
+            delay_factor: 1.0,
+            random_delay: None,
+            jitter: None,
+            max_duration: None,
+            handlers: vec![],
+            abort_conditions: vec![],
             tries: 0,
+            started: None,
+            next_delay: None,
+        }
+    }
+
+    /// Multiplies the delay by `delay_factor` after every failed attempt, starting from the
+    /// configured delay, until it reaches `max_delay`.
+    pub fn with_backoff(mut self, max_delay: Duration, delay_factor: f64) -> Self {
+        assert!(delay_factor >= 1.0, "delay_factor must be at least 1.0");
+        assert!(
+            self.random_delay.is_none(),
+            "backoff can't be combined with random delay"
+        );
+        assert!(
+            max_delay >= self.delay,
+            "max_delay must not be less than delay"
+        );
+        self.max_delay = Some(max_delay);
+        self.delay_factor = delay_factor;
+        self
+    }
+
+    /// Waits a random delay between `delay_min` and `delay_max` before every retry, instead of the
+    /// configured delay.
+    pub fn with_random_delay(mut self, delay_min: Duration, delay_max: Duration) -> Self {
+        assert!(
+            delay_min <= delay_max,
+            "delay_min must not be greater than delay_max"
+        );
+        assert!(
+            self.max_delay.is_none(),
+            "random delay can't be combined with backoff"
+        );
+        self.random_delay = Some((delay_min, delay_max));
+        self
+    }
+
+    /// Randomly changes each delay by up to `jitter_factor` of it in either direction, e.g. `0.1`
+    /// turns a 100ms delay into anything between 90ms and 110ms.
+    pub fn with_jitter_factor(mut self, jitter_factor: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&jitter_factor),
+            "jitter_factor must be between 0.0 and 1.0"
+        );
+        self.jitter = Some(Jitter::Factor(jitter_factor));
+        self
+    }
+
+    /// Randomly changes each delay by up to `jitter` in either direction.
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = Some(Jitter::Duration(jitter));
+        self
+    }
+
+    /// Stops retrying once `max_duration` has passed since the first attempt, regardless of the
+    /// retries left.
+    pub fn with_max_duration(mut self, max_duration: Duration) -> Self {
+        self.max_duration = Some(max_duration);
+        self
+    }
+
+    /// Retries runnable errors of type `E`.
+    pub fn handle<E: 'static>(self) -> Self {
+        self.handle_if(|error| error.is::<E>())
+    }
+
+    /// Retries runnable errors matching the predicate.
+    pub fn handle_if<F: Fn(&dyn Any) -> bool + Send + Sync + 'static>(
+        mut self,
+        predicate: F,
+    ) -> Self {
+        self.handlers.push(Arc::new(predicate));
+        self
+    }
+
+    /// Calls `listener` before every retry, with the error of the failed attempt.
+    pub fn on_retry<F: FnMut(&ExecutionEvent) + Send + 'static>(self, listener: F) -> Self {
+        self.policy_data
+            .listeners
+            .push(EventKind::Retry, Box::new(listener));
+        self
+    }
+
+    /// Calls `listener` when the retries or the max duration are exceeded, with the last error.
+    pub fn on_retries_exceeded<F: FnMut(&ExecutionEvent) + Send + 'static>(
+        self,
+        listener: F,
+    ) -> Self {
+        self.policy_data
+            .listeners
+            .push(EventKind::RetriesExceeded, Box::new(listener));
+        self
+    }
+
+    /// Fails immediately on runnable errors matching the predicate.
+    pub fn abort_on<F: Fn(&dyn Any) -> bool + Send + Sync + 'static>(
+        mut self,
+        predicate: F,
+    ) -> Self {
+        self.abort_conditions.push(Arc::new(predicate));
+        self
+    }
+
+    /// The retries, `None` when unlimited
+    pub fn retries(&self) -> Option<i32> {
+        self.retries
+    }
+    pub fn delay(&self) -> Duration {
+        self.delay
+    }
+    pub fn max_delay(&self) -> Option<Duration> {
+        self.max_delay
+    }
+    pub fn delay_factor(&self) -> f64 {
+        self.delay_factor
+    }
+    pub fn random_delay(&self) -> Option<(Duration, Duration)> {
+        self.random_delay
+    }
+    pub fn jitter(&self) -> Option<Jitter> {
+        self.jitter
+    }
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.max_duration
+    }
+
+    fn is_handled(&self, error: &FailsafeError) -> bool {
+        match error {
+            FailsafeError::RunnableError(e) => {
+                let e = e.as_ref();
+                !self.abort_conditions.iter().any(|abort| abort(e))
+                    && (self.handlers.is_empty() || self.handlers.iter().any(|handle| handle(e)))
+            }
+            _ => self.handlers.is_empty(),
+        }
+    }
+
+    fn retries_exceeded(&self) -> bool {
+        self.retries.is_some_and(|retries| self.tries >= retries)
+    }
+
+    /// Delay before the next attempt, given the number of failed attempts so far.
+    fn delay_for(&self, tries: i32) -> Duration {
+        let delay = self.base_delay_for(tries);
+        let jitter = match self.jitter {
+            Some(Jitter::Factor(factor)) => delay.as_secs_f64() * factor,
+            Some(Jitter::Duration(jitter)) => jitter.as_secs_f64(),
+            None => return delay,
         };
-        policy
+        let offset = rand::thread_rng().gen_range(-1.0..=1.0) * jitter;
+        Duration::from_secs_f64((delay.as_secs_f64() + offset).max(0.0))
+    }
+
+    fn base_delay_for(&self, tries: i32) -> Duration {
+        if let Some((delay_min, delay_max)) = self.random_delay {
+            return rand::thread_rng().gen_range(delay_min..=delay_max);
+        }
+        match self.max_delay {
+            Some(max_delay) => {
+                let factor = self.delay_factor.powi(tries - 1);
+                let delay = self.delay.as_secs_f64() * factor;
+                if delay.is_finite() && delay < max_delay.as_secs_f64() {
+                    Duration::from_secs_f64(delay)
+                } else {
+                    max_delay
+                }
+            }
+            None => self.delay,
+        }
     }
 }
 
@@ -52,24 +272,67 @@ impl Policy for RetryPolicy {
         "RetryPolicy".to_string()
     }
 
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn fork(&self) -> Box<dyn Policy> {
+        Box::new(self.clone())
+    }
+
+    fn before_run(&mut self) -> Result<(), FailsafeError> {
+        if self.started.is_none() {
+            self.started = Some(Instant::now());
+        }
+        Ok(())
+    }
+
     fn policy_action(
         &mut self,
         _: &mut Box<&mut dyn Runnable>,
+        error: &FailsafeError,
     ) -> Result<PolicyActionState, FailsafeError> {
+        if !self.is_handled(error) {
+            self.tries = 0;
+            self.started = None;
+            return Ok(PolicyActionState::Abort);
+        }
         self.tries += 1;
-        return if self.tries >= self.retries {
+        if self.retries_exceeded() {
             self.tries = 0;
-            Err(FailsafeError::RetryError)
-        } else {
-            sleep(self.delay);
-            Ok(PolicyActionState::Retry)
-        };
+            self.started = None;
+            self.emit(EventKind::RetriesExceeded, Some(error));
+            return Err(FailsafeError::RetryError);
+        }
+        let mut delay = self.delay_for(self.tries);
+        if let (Some(max_duration), Some(started)) = (self.max_duration, self.started) {
+            let elapsed = started.elapsed();
+            if elapsed >= max_duration {
+                self.tries = 0;
+                self.started = None;
+                self.emit(EventKind::RetriesExceeded, Some(error));
+                return Err(FailsafeError::RetryError);
+            }
+            delay = delay.min(max_duration - elapsed);
+        }
+        self.emit(EventKind::Retry, Some(error));
+        self.next_delay = Some(delay);
+        Ok(PolicyActionState::Retry)
+    }
+
+    fn take_retry_delay(&mut self) -> Option<Duration> {
+        self.next_delay.take()
     }
 
     fn reset(&mut self) {
         self.tries = 0;
-        self.inner_mut()
-            .as_mut()
-            .and_then(|mut inner| Some(inner.reset()));
+        self.started = None;
+        if let Some(inner) = self.inner_mut().as_mut() {
+            inner.reset();
+        }
     }
 }
@@ -1,23 +1,107 @@
+use crate::cancellation::CancellationToken;
+use crate::context::ExecutionContext;
+use crate::events::{EventKind, ExecutionEvent};
 use crate::failsafe_error::FailsafeError;
 use crate::policies::{Policy, PolicyData};
 use crate::run_state::PolicyActionState;
 use crate::Runnable;
 use std::any::Any;
-use std::borrow::Borrow;
+use std::panic;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
 use std::time::{Duration, Instant};
 
+/// Timeout policy, that fails executions taking longer than `timeout` with
+/// `FailsafeError::TimeoutError`
+///
+/// Every attempt gets a cancellation token that is cancelled at the deadline, which the runnable
+/// and the inner policies can check to stop early. Otherwise the runnable, or the inner policies,
+/// run to completion and are checked afterwards. With `with_interrupt`, runnables that can be
+/// detached run on another thread, and the policy fails as soon as the deadline passes, discarding
+/// their result. Only the innermost policy can interrupt, and closures can't be detached.
+#[derive(Clone)]
 pub struct TimeoutPolicy {
     timeout: Duration,
     policy_data: PolicyData,
-    time_taken: Option<Duration>,
+    interrupt: bool,
+    // start and token of the current attempt
+    started: Option<Instant>,
+    attempt_token: Option<CancellationToken>,
 }
 
 impl TimeoutPolicy {
-    pub(crate) fn new(timeout: Duration) -> Self {
+    pub fn new(timeout: Duration) -> Self {
         TimeoutPolicy {
             timeout,
             policy_data: Default::default(),
-            time_taken: None,
+            interrupt: false,
+            started: None,
+            attempt_token: None,
+        }
+    }
+
+    /// Stops waiting for the runnable once the deadline passes, see `Runnable::detach`. Has no
+    /// effect unless the policy is the innermost one, and the runnable can be detached.
+    pub fn with_interrupt(mut self) -> Self {
+        self.interrupt = true;
+        self
+    }
+
+    /// Calls `listener` when an attempt times out.
+    pub fn on_timeout<F: FnMut(&ExecutionEvent) + Send + 'static>(self, listener: F) -> Self {
+        self.policy_data
+            .listeners
+            .push(EventKind::Timeout, Box::new(listener));
+        self
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn is_interrupting(&self) -> bool {
+        self.interrupt
+    }
+
+    fn remaining(&self) -> Duration {
+        let started = self.started.unwrap_or_else(Instant::now);
+        self.timeout.saturating_sub(started.elapsed())
+    }
+
+    // the attempt timed out, as opposed to the whole execution being cancelled
+    fn is_timed_out(&self) -> bool {
+        self.remaining().is_zero() && !self.cancellation_token().is_cancelled()
+    }
+
+    fn timed_out(&mut self) -> Result<(), FailsafeError> {
+        if let Some(token) = &self.attempt_token {
+            token.cancel();
+        }
+        Err(FailsafeError::TimeoutError)
+    }
+
+    fn run_detached(
+        &mut self,
+        runnable: &mut dyn Runnable,
+        mut detached: Box<dyn Interruptable>,
+    ) -> Result<(), FailsafeError> {
+        let (sender, receiver) = mpsc::channel();
+        let worker = thread::spawn(move || {
+            let result = detached.run();
+            // the receiver is gone if the execution timed out
+            let _ = sender.send((detached, result));
+        });
+        let r = receiver.recv_timeout(self.remaining());
+        match r {
+            // the runnable may stop at the deadline just before the receiver does
+            Ok(_) if self.is_timed_out() => self.timed_out(),
+            Ok((detached, result)) => {
+                runnable.attach(&detached);
+                result.map_err(FailsafeError::RunnableError)
+            }
+            // the worker is left behind, its token tells it to stop
+            Err(RecvTimeoutError::Timeout) => self.timed_out(),
+            Err(RecvTimeoutError::Disconnected) => panic::resume_unwind(worker.join().unwrap_err()),
         }
     }
 }
@@ -35,13 +119,56 @@ impl Policy for TimeoutPolicy {
         "TimeoutPolicy".to_string()
     }
 
-    fn run_guarded(&mut self, runnable: &mut Box<&mut dyn Runnable>) -> Result<(), FailsafeError> {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn fork(&self) -> Box<dyn Policy> {
+        Box::new(self.clone())
+    }
+
+    fn before_run(&mut self) -> Result<(), FailsafeError> {
+        let started = Instant::now();
+        let token = self
+            .cancellation_token()
+            .child_with_deadline(started + self.timeout);
+        if let Some(inner) = self.inner_mut().as_mut() {
+            inner.set_cancellation_token(token.clone());
+        }
+        self.started = Some(started);
+        self.attempt_token = Some(token);
+        Ok(())
+    }
+
+    fn attempt_token(&self) -> CancellationToken {
+        match &self.attempt_token {
+            Some(token) => token.clone(),
+            None => self.cancellation_token().clone(),
+        }
+    }
+
+    fn attempt_timeout(&self) -> Option<Duration> {
+        Some(self.remaining())
+    }
+
+    fn run_guarded(
+        &mut self,
+        runnable: &mut Box<&mut dyn Runnable>,
+        context: &ExecutionContext,
+    ) -> Result<(), FailsafeError> {
+        if self.interrupt {
+            if let Some(detached) = runnable.detach() {
+                return self.run_detached(&mut ***runnable, detached);
+            }
+        }
         let start = Instant::now();
-        let r = runnable.run();
-        self.time_taken = Some(start.elapsed());
-        if self.time_taken > Some(self.timeout) {
-            self.policy_data.state = PolicyActionState::TimeoutError;
-            return Err(FailsafeError::TimeoutError);
+        let r = runnable.run_with_context(context);
+        if start.elapsed() > self.timeout || self.is_timed_out() {
+            return self.timed_out();
         }
         match r {
             Ok(_) => {}
@@ -50,24 +177,34 @@ impl Policy for TimeoutPolicy {
         Ok(())
     }
 
+    // the inner policies can't be interrupted, their outcome is discarded once the deadline passed
+    fn inner_finished(&mut self, result: Result<(), FailsafeError>) -> Result<(), FailsafeError> {
+        if self.is_timed_out() {
+            return self.timed_out();
+        }
+        result
+    }
+
     fn policy_action(
         &mut self,
         _: &mut Box<&mut dyn Runnable>,
+        error: &FailsafeError,
     ) -> Result<PolicyActionState, FailsafeError> {
-        match self.policy_data().state {
-            PolicyActionState::TimeoutError => Err(FailsafeError::TimeoutError),
-            _ => Ok(PolicyActionState::Success),
+        match error {
+            FailsafeError::TimeoutError => {}
+            // the inner policies stopped at the deadline
+            FailsafeError::Cancelled if self.is_timed_out() => {}
+            _ => return Ok(PolicyActionState::Abort),
         }
-    }
-
-    fn reset(&mut self) {
-        self.time_taken = None;
-        self.inner_mut()
-            .as_mut()
-            .and_then(|inner| Some(inner.reset()));
+        self.emit(EventKind::Timeout, Some(error));
+        Err(FailsafeError::TimeoutError)
     }
 }
 
-pub trait Interruptable {
+/// A copy of a runnable that can be run on another thread, and left behind if it times out.
+///
+/// Errors are boxed, and should hold the error type of the runnable.
+pub trait Interruptable<T = ()>: Send {
+    fn run(&mut self) -> Result<T, Box<dyn Any + Send>>;
     fn as_any(&self) -> &dyn Any;
 }
@@ -0,0 +1,23 @@
+use crate::failsafe_error::FailsafeError;
+use std::time::Duration;
+
+/// An error an attempt of a policy failed with, and that the policy acted upon, e.g. by retrying
+/// or falling back.
+#[derive(Debug)]
+pub struct PolicyError {
+    /// `Policy::name` of the policy that handled the error
+    pub policy: String,
+    /// The attempt of that policy which failed
+    pub attempt: u32,
+    pub error: FailsafeError,
+}
+
+/// The history of an execution, returned by `Failsafe::run_with_report` and `get_with_report`.
+#[derive(Debug, Default)]
+pub struct ExecutionReport {
+    /// The errors handled by the policies, in the order they happened, innermost policy first
+    pub errors: Vec<PolicyError>,
+    /// Attempts of the outermost policy
+    pub attempts: u32,
+    pub elapsed: Duration,
+}
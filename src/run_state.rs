@@ -9,6 +9,6 @@ pub enum PolicyActionState {
     Success,
     Retry,
     UsingFallback,
-    TimeoutError,
-    CircuitBreakerError,
+    // the policy doesn't handle the error, it's passed on as is
+    Abort,
 }
@@ -1,22 +1,32 @@
 use super::*;
+use crate::cancellation::CancellationToken;
+use crate::context::ExecutionContext;
+use crate::events::ExecutionEvent;
 use crate::person::{Person, PersonError};
+use crate::policies::bulkhead::BulkheadPolicy;
 use crate::policies::circuit_breaker::{CircuitBreakerPolicy, CircuitBreakerState};
+use crate::policies::fallback::FallbackAble;
 use crate::policies::rate_limiter::{LimiterType, RateLimiter};
+use crate::policies::timeout::Interruptable;
+use crate::policies::PolicyData;
+use crate::run_state::PolicyActionState;
 use crate::{
     failsafe::Failsafe,
     failsafe_error::FailsafeError,
     policies::Policy,
     policies::{fallback::FallbackPolicy, retry::RetryPolicy, timeout::TimeoutPolicy},
 };
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
 use std::thread::sleep;
 use std::time::{Duration, Instant};
 
-fn check_expected_error(r: Result<(), FailsafeError>, expected: &str) -> bool {
+fn check_expected_error<T, E: Debug>(r: Result<T, FailsafeError<E>>, expected: &str) -> bool {
     match r {
         Ok(_) => false,
         Err(e) => {
             let s = format!("{:?}", e);
-            &s == expected
+            s == expected
         }
     }
 }
@@ -24,7 +34,7 @@ fn check_expected_error(r: Result<(), FailsafeError>, expected: &str) -> bool {
 #[test]
 fn fallback_callback() {
     let mut k = 0;
-    let mut safe = failsafe!([
+    let safe = failsafe!([
         RetryPolicy; [1, Duration::from_millis(50)],
         FallbackPolicy; [on_fallback!({
             k += 1;
@@ -37,26 +47,72 @@ fn fallback_callback() {
     ]);
     let mut person = Person::new();
     person.set_always_fail(true);
-    let person_result = { safe.run(&mut person) };
+    let _ = { safe.run(&mut person) };
     assert_eq!("Person 1", person.name());
-    let person_result = { safe.run(&mut person) };
+    let _ = { safe.run(&mut person) };
     assert_eq!("Person 2", person.name());
-    let person_result = { safe.run(&mut person) };
+    let _ = { safe.run(&mut person) };
     assert_eq!("Person 3", person.name());
 }
 
 #[test]
 fn test_fallback() {
-    let mut safe = failsafe!([FallbackPolicy; [on_fallback!({ Person::with_name("No Name") })]]);
+    let safe = failsafe!([FallbackPolicy; [on_fallback!(Person::with_name("No Name"))]]);
     let mut person = Person::new();
     person.set_always_fail(true);
     let person_result = { safe.run(&mut person) };
     assert!(check_expected_error(person_result, "UsedFallback"));
 }
 
+#[test]
+fn panicking_fallback_leaves_the_policy_usable() {
+    let mut calls = 0;
+    let safe = Failsafe::builder()
+        .push(FallbackPolicy::of_value(move || {
+            calls += 1;
+            if calls == 1 {
+                panic!("fallback failed");
+            }
+            "No Name".to_string()
+        }))
+        .build();
+    let fail = || Err::<String, _>(PersonError::NameFindingError);
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| safe.get(fail)));
+    assert!(panicked.is_err());
+    assert_eq!(safe.get(fail).unwrap(), "No Name");
+}
+
+#[test]
+fn fallback_values() {
+    let safe = Failsafe::builder()
+        .push(FallbackPolicy::of_value(|| "No Name".to_string()))
+        .push(RetryPolicy::new(2, Duration::from_millis(1)))
+        .build();
+    let name = safe.get(|| Err::<String, _>(PersonError::NameFindingError));
+    assert_eq!(name.unwrap(), "No Name");
+    // the runnable's own value wins when it succeeds
+    let name = safe.get(|| Ok::<_, PersonError>("Person".to_string()));
+    assert_eq!(name.unwrap(), "Person");
+
+    // runnables without a value fall back too
+    let mut person = Person::new();
+    person.set_always_fail(true);
+    let safe = Failsafe::builder()
+        .push(FallbackPolicy::of_value(|| ()))
+        .build();
+    assert!(safe.run(&mut person).is_ok());
+
+    // values of another type can't be returned
+    let safe = Failsafe::builder()
+        .push(FallbackPolicy::of_value(|| 0))
+        .build();
+    let name = safe.get(|| Err::<String, _>(PersonError::NameFindingError));
+    assert!(check_expected_error(name, "UsedFallback"));
+}
+
 #[test]
 fn test_retry_policy_with_always_failing() {
-    let mut safe = failsafe!([RetryPolicy; [3, Duration::from_millis(50)]]);
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(50)]]);
     let mut person = Person::new();
     person.set_always_fail(true);
     let person_result = { safe.run(&mut person) };
@@ -65,7 +121,7 @@ fn test_retry_policy_with_always_failing() {
 
 #[test]
 fn test_retry_policy_working_after_few_retries() {
-    let mut safe = failsafe!([RetryPolicy; [3, Duration::from_millis(50)]]);
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(50)]]);
     let mut person = Person::new();
     person.set_fail_pattern(vec![false, true, true, false]);
     let person_result = { safe.run(&mut person) };
@@ -74,19 +130,19 @@ fn test_retry_policy_working_after_few_retries() {
 
 #[test]
 fn retry_policy_on_fail() {
-    let mut safe = failsafe!([RetryPolicy; [3, Duration::from_millis(50)]]);
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(50)]]);
     let mut person = Person::new();
     person.set_always_fail(true);
     let person_result = { safe.run(&mut person) };
     assert_eq!(
         person_result.expect_err("What error!").to_string(),
-        FailsafeError::RetryError.to_string()
+        FailsafeError::<Box<dyn Any>>::RetryError.to_string()
     );
 }
 
 #[test]
 fn test_if_retry_policy_multiple_run_correctly_reset() {
-    let mut safe = failsafe!([RetryPolicy; [3, Duration::from_millis(50)]]);
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(50)]]);
     let mut person = Person::new();
     person.set_fail_pattern(vec![false, true, true]);
     let person_result = { safe.run(&mut person) };
@@ -100,9 +156,9 @@ fn test_if_retry_policy_multiple_run_correctly_reset() {
 
 #[test]
 fn test_using_different_value_from_fallback() {
-    let mut safe = {
+    let safe = {
         let mut k = 0;
-        let name_list = vec!["", "Picard", "Riker", "Data"];
+        let name_list = ["", "Picard", "Riker", "Data"];
         failsafe!([
             RetryPolicy; [1, Duration::from_millis(50)],
             FallbackPolicy; [on_fallback!({
@@ -134,7 +190,7 @@ fn failsafe_builder_marco() {
         RetryPolicy; [3, Duration::from_millis(50)]
     ]);
     assert_eq!(safe.policy().name(), "FallbackPolicy");
-    let mut p = safe.policy().as_ref().clone();
+    let p = safe.policy().as_ref();
     let k = p.inner().as_ref().unwrap().name();
     assert_eq!(&k, "RetryPolicy");
 }
@@ -148,15 +204,16 @@ fn failsafe_builder() {
         .push(RetryPolicy::new(3, Duration::from_millis(50)))
         .build();
     assert_eq!(safe.policy().name(), "FallbackPolicy");
-    let mut p = safe.policy().as_ref().clone();
+    let p = safe.policy().as_ref();
     let k = p.inner().as_ref().unwrap().name();
     assert_eq!(&k, "RetryPolicy");
 }
 
 #[test]
 fn timeout_policy_test() {
-    let mut safe = failsafe!([TimeoutPolicy; [Duration::from_millis(1000)]]);
+    let safe = failsafe!([TimeoutPolicy; [Duration::from_millis(1000)]]);
     let mut person = Person::new();
+    person.set_fail_pattern(vec![false]);
     let person_result = { safe.run(&mut person) };
     assert!(person_result.is_ok());
 
@@ -166,6 +223,7 @@ fn timeout_policy_test() {
     check_expected_error(person_result, "TimeoutError");
 
     person.set_wait_for(Duration::from_millis(100));
+    person.set_fail_pattern(vec![]);
     person.set_always_fail(true);
     let person_result = { safe.run(&mut person) };
     assert!(person_result.is_err());
@@ -174,6 +232,31 @@ fn timeout_policy_test() {
     }
 }
 
+#[test]
+fn interrupting_timeout_policy() {
+    let safe = failsafe!([TimeoutPolicy; [Duration::from_millis(100)].with_interrupt()]);
+    let mut person = Person::new();
+    person.set_fail_pattern(vec![false]);
+    person.set_wait_for(Duration::from_millis(10));
+    assert!(safe.run(&mut person).is_ok());
+    // the detached copy's name is taken over
+    assert_eq!(person.name().len(), 16);
+
+    person.set_wait_for(Duration::from_secs(2));
+    let start = Instant::now();
+    assert!(check_expected_error(safe.run(&mut person), "TimeoutError"));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    person.set_wait_for(Duration::from_millis(10));
+    person.set_fail_pattern(vec![true]);
+    match safe.run(&mut person) {
+        Err(FailsafeError::RunnableError(e)) => {
+            assert_eq!(&PersonError::NameFindingError, PersonError::from_any(&e))
+        }
+        _ => panic!("expected a runnable error"),
+    }
+}
+
 #[test]
 fn circuit_breaker_impl() {
     let mut person = Person::new();
@@ -202,14 +285,14 @@ fn circuit_breaker_impl() {
     }
     println!("> {:?}", policy_errors);
     policy_errors.clear();
-    assert_eq!(policy.circuit_breaker_state(), &CircuitBreakerState::Open);
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Open);
     for _ in 0..=2 {
         println!("Running ...");
         let r = policy.run(&mut Box::new(&mut person), &mut policy_errors);
         if let Err(FailsafeError::CircuitBreakerOpen) = r {
             continue;
         }
-        assert!(false)
+        panic!("circuit breaker should be open")
     }
     sleep(Duration::from_millis(22));
     for _ in 0..1 {
@@ -218,13 +301,13 @@ fn circuit_breaker_impl() {
             .is_ok());
         assert_eq!(
             policy.circuit_breaker_state(),
-            &CircuitBreakerState::HalfOpen
+            CircuitBreakerState::HalfOpen
         );
     }
     assert!(policy
         .run(&mut Box::new(&mut person), &mut policy_errors)
         .is_ok());
-    assert_eq!(policy.circuit_breaker_state(), &CircuitBreakerState::Closed);
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Closed);
     person.set_fail_pattern(vec![]);
     person.set_always_fail(true);
     for _ in 0..=5 {
@@ -235,13 +318,10 @@ fn circuit_breaker_impl() {
     }
     println!("> {:?}", policy_errors);
     policy_errors.clear();
-    if let Err(FailsafeError::CircuitBreakerOpen) =
-        policy.run(&mut Box::new(&mut person), &mut policy_errors)
-    {
-        assert!(true);
-    } else {
-        assert!(false);
-    }
+    assert!(matches!(
+        policy.run(&mut Box::new(&mut person), &mut policy_errors),
+        Err(FailsafeError::CircuitBreakerOpen)
+    ));
     sleep(Duration::from_millis(22));
     person.set_fail_pattern(vec![false, true]);
     assert!(policy
@@ -250,7 +330,7 @@ fn circuit_breaker_impl() {
     assert!(policy
         .run(&mut Box::new(&mut person), &mut policy_errors)
         .is_err());
-    assert_eq!(policy.circuit_breaker_state(), &CircuitBreakerState::Open);
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Open);
     println!("{:?}", policy_errors);
 }
 
@@ -258,12 +338,1412 @@ fn circuit_breaker_impl() {
 fn rate_limiter_impl() {
     let mut policy = RateLimiter::new(LimiterType::Smooth, 100, Duration::from_secs(1));
     let mut p = Person::new();
+    p.set_fail_pattern(vec![false]);
     let mut policy_errors = vec![];
     let start = Instant::now();
+    let mut oks = 0;
     for _ in 0..200 {
         match policy.run(&mut Box::new(&mut p), &mut policy_errors) {
-            Ok(_) => {}
-            Err(_) => {}
+            Ok(_) => oks += 1,
+            Err(e) => assert!(matches!(e, FailsafeError::RateLimitExceeded)),
         }
+        sleep(Duration::from_millis(1));
+    }
+    // one permit every 10 ms
+    let allowed = start.elapsed().as_millis() / 10 + 1;
+    assert!(oks >= 1 && oks <= allowed, "{} of {} allowed", oks, allowed);
+    assert!(oks < 200);
+}
+
+#[test]
+fn bursty_rate_limiter() {
+    let limiter = RateLimiter::new(LimiterType::Burst, 5, Duration::from_millis(200));
+    let safe = Failsafe::builder().push(limiter).build();
+    let mut p = Person::new();
+    p.set_fail_pattern(vec![false]);
+    for _ in 0..5 {
+        assert!(safe.run(&mut p).is_ok());
+    }
+    assert!(check_expected_error(safe.run(&mut p), "RateLimitExceeded"));
+    sleep(Duration::from_millis(250));
+    for _ in 0..5 {
+        assert!(safe.run(&mut p).is_ok());
     }
+    assert!(check_expected_error(safe.run(&mut p), "RateLimitExceeded"));
+}
+
+#[test]
+fn rate_limiter_with_max_wait_time() {
+    let limiter = RateLimiter::new(LimiterType::Smooth, 10, Duration::from_secs(1))
+        .with_max_wait_time(Duration::from_millis(150));
+    let safe = Failsafe::builder().push(limiter).build();
+    let mut p = Person::new();
+    p.set_fail_pattern(vec![false]);
+    assert!(safe.run(&mut p).is_ok());
+    // waits for the next permit, 100 ms later
+    let start = Instant::now();
+    assert!(safe.run(&mut p).is_ok());
+    assert!(start.elapsed() >= Duration::from_millis(90));
+
+    let limiter = RateLimiter::new(LimiterType::Burst, 1, Duration::from_secs(1))
+        .with_max_wait_time(Duration::from_millis(50));
+    let safe = Failsafe::builder().push(limiter).build();
+    assert!(safe.run(&mut p).is_ok());
+    // next period is too far away, gives up right away
+    let start = Instant::now();
+    assert!(check_expected_error(safe.run(&mut p), "RateLimitExceeded"));
+    assert!(start.elapsed() < Duration::from_millis(50));
+}
+
+#[test]
+fn smooth_rate_limiter_shares_permits() {
+    let limiter = RateLimiter::new(LimiterType::Smooth, 10, Duration::from_millis(500));
+    let safe = Failsafe::builder().push(limiter.clone()).build();
+    let mut p = Person::new();
+    p.set_fail_pattern(vec![false]);
+    assert!(safe.run(&mut p).is_ok());
+    assert!(check_expected_error(safe.run(&mut p), "RateLimitExceeded"));
+    sleep(limiter.interval());
+    assert!(safe.run(&mut p).is_ok());
+}
+
+#[test]
+fn retry_policy_with_backoff() {
+    let safe = failsafe!(
+        [RetryPolicy; [4, Duration::from_millis(10)].with_backoff(Duration::from_millis(30), 2.0)]
+    );
+    let mut person = Person::new();
+    person.set_always_fail(true);
+    let start = Instant::now();
+    let person_result = { safe.run(&mut person) };
+    // 10ms, 20ms and then capped at 30ms
+    assert!(start.elapsed() >= Duration::from_millis(60));
+    assert!(check_expected_error(person_result, "RetryError"));
+
+    let safe = Failsafe::builder()
+        .push(
+            RetryPolicy::new(3, Duration::from_millis(10))
+                .with_backoff(Duration::from_secs(1), 1.5),
+        )
+        .build();
+    assert_eq!(safe.policy().name(), "RetryPolicy");
+}
+
+#[test]
+fn retry_policy_with_random_delay_and_jitter() {
+    let safe = failsafe!(
+        [RetryPolicy;
+            [3, Duration::from_millis(0)]
+                .with_random_delay(Duration::from_millis(20), Duration::from_millis(40))]
+    );
+    let mut person = Person::new();
+    person.set_always_fail(true);
+    let start = Instant::now();
+    let _ = { safe.run(&mut person) };
+    assert!(start.elapsed() >= Duration::from_millis(40));
+
+    let safe = failsafe!(
+        [RetryPolicy; [3, Duration::from_millis(50)].with_jitter(Duration::from_millis(25))]
+    );
+    let start = Instant::now();
+    let _ = { safe.run(&mut person) };
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    let safe = failsafe!(
+        [RetryPolicy;
+            [3, Duration::from_millis(50)]
+                .with_backoff(Duration::from_millis(100), 2.0)
+                .with_jitter_factor(0.5)]
+    );
+    let start = Instant::now();
+    let person_result = { safe.run(&mut person) };
+    // 50ms and 100ms, each at least halved
+    assert!(start.elapsed() >= Duration::from_millis(75));
+    assert!(check_expected_error(person_result, "RetryError"));
+}
+
+#[test]
+fn retry_policy_without_limit() {
+    let safe = Failsafe::builder()
+        .push(RetryPolicy::unlimited(Duration::from_millis(1)))
+        .build();
+    let mut person = Person::new();
+    let mut pattern = vec![true; 20];
+    pattern.push(false);
+    person.set_fail_pattern(pattern);
+    assert!(safe.run(&mut person).is_ok());
+}
+
+#[test]
+#[should_panic(expected = "retries must not be negative")]
+fn retry_policy_rejects_negative_retries() {
+    RetryPolicy::new(-1, Duration::from_millis(1));
+}
+
+#[test]
+fn retry_policy_with_max_duration() {
+    let safe = Failsafe::builder()
+        .push(
+            RetryPolicy::unlimited(Duration::from_millis(20))
+                .with_max_duration(Duration::from_millis(100)),
+        )
+        .build();
+    let mut person = Person::new();
+    person.set_always_fail(true);
+    let start = Instant::now();
+    let person_result = { safe.run(&mut person) };
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(start.elapsed() < Duration::from_millis(200));
+    assert!(check_expected_error(person_result, "RetryError"));
+
+    // budget is measured per execution
+    let start = Instant::now();
+    let person_result = { safe.run(&mut person) };
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(check_expected_error(person_result, "RetryError"));
+}
+
+#[test]
+fn retry_policy_handles_selected_errors() {
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(10)].handle::<PersonError>()]);
+    let mut person = Person::new();
+    person.set_fail_pattern(vec![true, true, false]);
+    assert!(safe.run(&mut person).is_ok());
+
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(100)].handle::<String>()]);
+    person.set_fail_pattern(vec![true, false]);
+    let start = Instant::now();
+    let person_result = { safe.run(&mut person) };
+    assert!(start.elapsed() < Duration::from_millis(100));
+    match person_result {
+        Err(FailsafeError::RunnableError(e)) => {
+            assert_eq!(&PersonError::NameFindingError, PersonError::from_any(&e))
+        }
+        _ => panic!("runnable error should not be retried"),
+    }
+}
+
+#[test]
+fn retry_policy_aborts_on_error() {
+    let safe = failsafe!(
+        [RetryPolicy; [3, Duration::from_millis(100)].abort_on(|e| e.is::<PersonError>())]
+    );
+    let mut person = Person::new();
+    person.set_fail_pattern(vec![true, false]);
+    let start = Instant::now();
+    let person_result = { safe.run(&mut person) };
+    assert!(start.elapsed() < Duration::from_millis(100));
+    assert!(matches!(
+        person_result,
+        Err(FailsafeError::RunnableError(_))
+    ));
+    // the next execution starts from the first attempt again
+    assert!(safe.run(&mut person).is_ok());
+
+    let safe = failsafe!([
+        RetryPolicy; [3, Duration::from_millis(100)].handle::<PersonError>(),
+        CircuitBreakerPolicy; [1, Duration::from_secs(10), 1]
+    ]);
+    person.set_always_fail(true);
+    person.set_fail_pattern(vec![]);
+    let _ = { safe.run(&mut person) };
+    let start = Instant::now();
+    let person_result = { safe.run(&mut person) };
+    assert!(start.elapsed() < Duration::from_millis(100));
+    assert!(check_expected_error(person_result, "CircuitBreakerOpen"));
+}
+
+#[test]
+fn time_based_circuit_breaker() {
+    let mut policy = CircuitBreakerPolicy::new(3, Duration::from_millis(20), 1)
+        .with_failure_period(Duration::from_millis(100));
+    let mut person = Person::new();
+    let mut policy_errors = vec![];
+    // failures are counted even with successes in between
+    person.set_fail_pattern(vec![true, false, true, false]);
+    for _ in 0..4 {
+        let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    }
+    assert_eq!(policy.failure_count(), 2);
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Closed);
+    // but not after they left the period
+    sleep(Duration::from_millis(110));
+    assert_eq!(policy.failure_count(), 0);
+    person.set_fail_pattern(vec![true, true, true]);
+    for _ in 0..2 {
+        let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    }
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Closed);
+    let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Open);
+    assert!(matches!(
+        policy.run(&mut Box::new(&mut person), &mut policy_errors),
+        Err(FailsafeError::CircuitBreakerOpen)
+    ));
+
+    sleep(Duration::from_millis(22));
+    person.set_fail_pattern(vec![false]);
+    assert!(policy
+        .run(&mut Box::new(&mut person), &mut policy_errors)
+        .is_ok());
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Closed);
+    assert_eq!(policy.failure_count(), 0);
+}
+
+#[test]
+fn circuit_breaker_listeners_may_panic_and_use_the_breaker() {
+    let breaker = CircuitBreakerPolicy::new(1, Duration::from_secs(1), 1);
+    let control = breaker.clone();
+    let opened = Arc::new(Mutex::new(0));
+    let closed = Arc::new(Mutex::new(0));
+    let breaker = breaker
+        .on_open({
+            let opened = opened.clone();
+            move |_, _, _| {
+                *opened.lock().unwrap() += 1;
+                if *opened.lock().unwrap() == 1 {
+                    panic!("listener failed");
+                }
+                control.close();
+            }
+        })
+        .on_close({
+            let closed = closed.clone();
+            move |_, _, _| *closed.lock().unwrap() += 1
+        });
+    let safe = Failsafe::builder().push(breaker.clone()).build();
+    let fail = || Err::<(), _>(PersonError::NameFindingError);
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| safe.run_fn(fail)));
+    assert!(panicked.is_err());
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Open);
+    breaker.half_open();
+    assert!(safe.run_fn(fail).is_err());
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Closed);
+    assert_eq!((*opened.lock().unwrap(), *closed.lock().unwrap()), (2, 1));
+}
+
+#[test]
+fn circuit_breaker_delay_starts_when_opened() {
+    let safe = failsafe!([CircuitBreakerPolicy; [1, Duration::from_millis(50), 1]]);
+    // the attempt takes longer than the delay before failing
+    let _ = safe.get(|| {
+        sleep(Duration::from_millis(80));
+        Err::<(), _>(PersonError::NameFindingError)
+    });
+    let breaker = safe.find_policy::<CircuitBreakerPolicy>().unwrap();
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Open);
+    assert!(breaker.metrics().remaining_delay > Duration::from_millis(30));
+    assert!(matches!(
+        safe.get(|| Ok::<_, PersonError>(())),
+        Err(FailsafeError::CircuitBreakerOpen)
+    ));
+}
+
+#[test]
+fn time_based_circuit_breaker_counts_in_buckets() {
+    let safe = failsafe!(
+        [CircuitBreakerPolicy;
+            [1000, Duration::from_millis(20), 1].with_failure_period(Duration::from_millis(100))]
+    );
+    let breaker = safe.find_policy::<CircuitBreakerPolicy>().unwrap();
+    for i in 0..500 {
+        let _ = safe.get(|| {
+            if i % 5 == 0 {
+                Err(PersonError::NameFindingError)
+            } else {
+                Ok(())
+            }
+        });
+    }
+    let metrics = breaker.metrics();
+    assert_eq!((metrics.executions, metrics.failures), (500, 100));
+    sleep(Duration::from_millis(110));
+    let metrics = breaker.metrics();
+    assert_eq!((metrics.executions, metrics.failures), (0, 0));
+}
+
+#[test]
+fn failure_rate_circuit_breaker() {
+    let mut policy =
+        CircuitBreakerPolicy::new(1, Duration::from_millis(20), 1).with_failure_rate(0.5, 4, 6);
+    let mut person = Person::new();
+    let mut policy_errors = vec![];
+    // below minimum executions, even though the only failure is above the rate
+    person.set_fail_pattern(vec![false, false, true, false, false, false, true, true]);
+    for _ in 0..3 {
+        let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    }
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Closed);
+    // 2 out of 6
+    for _ in 0..4 {
+        let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    }
+    assert_eq!(policy.failure_count(), 2);
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Closed);
+    // 3 out of the last 6
+    let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Open);
+}
+
+#[test]
+fn failure_ratio_circuit_breaker() {
+    let mut policy =
+        CircuitBreakerPolicy::new(5, Duration::from_millis(20), 1).with_failure_ratio(2, 3);
+    let mut person = Person::new();
+    let mut policy_errors = vec![];
+    person.set_fail_pattern(vec![true, false, false, true, true]);
+    for _ in 0..4 {
+        let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    }
+    // the first failure already left the window
+    assert_eq!(policy.failure_count(), 1);
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Closed);
+    let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Open);
+}
+
+#[test]
+fn circuit_breaker_metrics() {
+    let mut policy = CircuitBreakerPolicy::new(2, Duration::from_millis(50), 1);
+    let mut person = Person::new();
+    let mut policy_errors = vec![];
+    person.set_fail_pattern(vec![false, true, true, false]);
+    for _ in 0..3 {
+        let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    }
+    let metrics = policy.metrics();
+    assert_eq!(metrics.state, CircuitBreakerState::Open);
+    assert_eq!(metrics.executions, 3);
+    assert_eq!(metrics.failures, 2);
+    assert_eq!(metrics.successes, 1);
+    assert!((metrics.failure_rate - 2.0 / 3.0).abs() < f64::EPSILON);
+    assert!(metrics.remaining_delay > Duration::ZERO);
+    assert!(metrics.remaining_delay <= Duration::from_millis(50));
+    assert_eq!(
+        (metrics.opened, metrics.half_opened, metrics.closed),
+        (1, 0, 0)
+    );
+
+    sleep(Duration::from_millis(55));
+    assert_eq!(policy.metrics().remaining_delay, Duration::ZERO);
+    assert!(policy
+        .run(&mut Box::new(&mut person), &mut policy_errors)
+        .is_ok());
+    let metrics = policy.metrics();
+    assert_eq!(metrics.state, CircuitBreakerState::Closed);
+    assert_eq!(metrics.executions, 0);
+    assert_eq!(metrics.failure_rate, 0.0);
+    assert!(metrics.time_in_state < Duration::from_millis(50));
+    assert_eq!(
+        (metrics.opened, metrics.half_opened, metrics.closed),
+        (1, 1, 1)
+    );
+}
+
+#[test]
+fn circuit_breaker_listeners() {
+    let transitions = Arc::new(Mutex::new(vec![]));
+    let (on_open, on_half_open, on_close) = (
+        transitions.clone(),
+        transitions.clone(),
+        transitions.clone(),
+    );
+    let mut policy = CircuitBreakerPolicy::new(2, Duration::from_millis(20), 1)
+        .on_open(move |from, to, metrics| {
+            assert_eq!(metrics.failures, 2);
+            on_open.lock().unwrap().push((*from, *to));
+        })
+        .on_half_open(move |from, to, _| on_half_open.lock().unwrap().push((*from, *to)))
+        .on_close(move |from, to, metrics| {
+            assert_eq!(metrics.successes, 1);
+            on_close.lock().unwrap().push((*from, *to));
+        });
+    let mut person = Person::new();
+    let mut policy_errors = vec![];
+    person.set_fail_pattern(vec![true, true, false]);
+    for _ in 0..3 {
+        let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    }
+    assert_eq!(
+        transitions.lock().unwrap().as_slice(),
+        &[(CircuitBreakerState::Closed, CircuitBreakerState::Open)]
+    );
+    sleep(Duration::from_millis(22));
+    let _ = policy.run(&mut Box::new(&mut person), &mut policy_errors);
+    assert_eq!(
+        transitions.lock().unwrap().as_slice(),
+        &[
+            (CircuitBreakerState::Closed, CircuitBreakerState::Open),
+            (CircuitBreakerState::Open, CircuitBreakerState::HalfOpen),
+            (CircuitBreakerState::HalfOpen, CircuitBreakerState::Closed),
+        ]
+    );
+}
+
+#[test]
+fn circuit_breaker_manual_control() {
+    let safe = failsafe!([
+        RetryPolicy; [2, Duration::from_millis(1)],
+        CircuitBreakerPolicy; [1, Duration::from_millis(10), 1]
+    ]);
+    assert!(safe.find_policy::<TimeoutPolicy>().is_none());
+    let mut person = Person::new();
+    person.set_fail_pattern(vec![false]);
+
+    safe.find_policy::<CircuitBreakerPolicy>().unwrap().open();
+    sleep(Duration::from_millis(15));
+    // stays open after the delay
+    assert!(check_expected_error(safe.run(&mut person), "RetryError"));
+    let breaker = safe.find_policy::<CircuitBreakerPolicy>().unwrap();
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Open);
+    assert_eq!(breaker.metrics().executions, 0);
+
+    let breaker = safe.find_policy::<CircuitBreakerPolicy>().unwrap();
+    breaker.close();
+    assert!(breaker.is_forced());
+    person.set_fail_pattern(vec![]);
+    person.set_always_fail(true);
+    assert!(check_expected_error(safe.run(&mut person), "RetryError"));
+    // failures don't open it
+    let breaker = safe.find_policy::<CircuitBreakerPolicy>().unwrap();
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Closed);
+    assert_eq!(breaker.metrics().failures, 2);
+
+    safe.find_policy::<CircuitBreakerPolicy>()
+        .unwrap()
+        .release();
+    let _ = safe.run(&mut person);
+    let breaker = safe.find_policy::<CircuitBreakerPolicy>().unwrap();
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Open);
+
+    breaker.half_open();
+    assert!(!breaker.is_forced());
+    person.set_always_fail(false);
+    person.set_fail_pattern(vec![false]);
+    assert!(safe.run(&mut person).is_ok());
+    assert_eq!(
+        safe.find_policy::<CircuitBreakerPolicy>()
+            .unwrap()
+            .circuit_breaker_state(),
+        CircuitBreakerState::Closed
+    );
+}
+
+#[test]
+fn circuit_breaker_half_open_permits() {
+    let mut policy =
+        CircuitBreakerPolicy::new(1, Duration::from_millis(10), 2).with_half_open_permits(1);
+    assert_eq!(policy.half_open_permits(), 1);
+    policy.half_open();
+    // a trial is in flight until its outcome is recorded, other executions are rejected
+    assert!(policy.before_run().is_ok());
+    let mut other = policy.fork();
+    assert!(matches!(
+        other.before_run(),
+        Err(FailsafeError::CircuitBreakerOpen)
+    ));
+    policy.after_success();
+    assert_eq!(
+        policy.circuit_breaker_state(),
+        CircuitBreakerState::HalfOpen
+    );
+    assert!(other.before_run().is_ok());
+    other.after_success();
+    assert_eq!(policy.circuit_breaker_state(), CircuitBreakerState::Closed);
+    // no limit when closed
+    assert!(policy.before_run().is_ok());
+    assert!(other.before_run().is_ok());
+}
+
+#[test]
+fn circuit_breaker_releases_only_acquired_trials() {
+    let mut closed =
+        CircuitBreakerPolicy::new(1, Duration::from_millis(10), 2).with_half_open_permits(1);
+    // admitted while closed, finishing after the breaker half-opened
+    assert!(closed.before_run().is_ok());
+    closed.half_open();
+    let mut trial = closed.fork();
+    assert!(trial.before_run().is_ok());
+    closed.after_success();
+    assert!(matches!(
+        closed.fork().before_run(),
+        Err(FailsafeError::CircuitBreakerOpen)
+    ));
+    trial.after_success();
+    assert!(closed.fork().before_run().is_ok());
+
+    // a panicking trial releases its permit
+    let breaker =
+        CircuitBreakerPolicy::new(1, Duration::from_millis(10), 1).with_half_open_permits(1);
+    let safe = Failsafe::builder().push(breaker.clone()).build();
+    breaker.half_open();
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        safe.run_fn(|| -> Result<(), PersonError> { panic!("runnable failed") })
+    }));
+    assert!(panicked.is_err());
+    assert!(safe.run_fn(|| Ok::<_, PersonError>(())).is_ok());
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Closed);
+}
+
+#[test]
+fn failsafe_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Failsafe>();
+
+    let breaker = CircuitBreakerPolicy::new(8, Duration::from_secs(10), 1);
+    let safe = Arc::new(
+        Failsafe::builder()
+            .push(RetryPolicy::new(2, Duration::from_millis(1)))
+            .push(breaker.clone())
+            .build(),
+    );
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let safe = safe.clone();
+            std::thread::spawn(move || {
+                let mut person = Person::new();
+                person.set_always_fail(true);
+                safe.run(&mut person).is_err()
+            })
+        })
+        .collect();
+    for worker in workers {
+        assert!(worker.join().unwrap());
+    }
+    // every thread counted towards the same breaker
+    assert_eq!(breaker.metrics().failures, 8);
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Open);
+
+    // and so do clones
+    let cloned = (*safe).clone();
+    let mut person = Person::new();
+    person.set_fail_pattern(vec![false]);
+    assert!(check_expected_error(cloned.run(&mut person), "RetryError"));
+    breaker.close();
+    assert!(cloned.run(&mut person).is_ok());
+    assert!(safe.run(&mut person).is_ok());
+}
+
+#[test]
+fn bulkhead_limits_concurrent_executions() {
+    fn run_concurrently(safe: &Arc<Failsafe>, n: usize) -> Vec<Result<(), FailsafeError>> {
+        let workers: Vec<_> = (0..n)
+            .map(|_| {
+                let safe = safe.clone();
+                std::thread::spawn(move || {
+                    let mut person = Person::new();
+                    person.set_fail_pattern(vec![false]);
+                    person.set_wait_for(Duration::from_millis(200));
+                    safe.run(&mut person)
+                })
+            })
+            .collect();
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    }
+
+    let bulkhead = BulkheadPolicy::new(2);
+    let safe = Arc::new(Failsafe::builder().push(bulkhead.clone()).build());
+    let results = run_concurrently(&safe, 4);
+    assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2);
+    let full = results
+        .iter()
+        .filter(|r| matches!(r, Err(FailsafeError::BulkheadFull)))
+        .count();
+    assert_eq!(full, 2);
+    assert_eq!(bulkhead.running(), 0);
+
+    // waiting for the running executions to finish lets all of them through
+    let bulkhead = BulkheadPolicy::new(2).with_max_wait_time(Duration::from_secs(1));
+    let safe = Arc::new(Failsafe::builder().push(bulkhead.clone()).build());
+    let results = run_concurrently(&safe, 4);
+    assert!(results.iter().all(|r| r.is_ok()));
+    assert_eq!(bulkhead.running(), 0);
+}
+
+#[test]
+fn cancellation_tokens_propagate() {
+    let parent = CancellationToken::new();
+    let child = parent.child();
+    let other = parent.child();
+    child.cancel();
+    assert!(child.is_cancelled());
+    assert!(!parent.is_cancelled() && !other.is_cancelled());
+
+    parent.cancel();
+    assert!(other.is_cancelled());
+
+    let deadline =
+        CancellationToken::new().child_with_deadline(Instant::now() + Duration::from_millis(20));
+    assert!(!deadline.is_cancelled());
+    sleep(Duration::from_millis(30));
+    assert!(deadline.is_cancelled());
+}
+
+#[test]
+fn cancelling_from_another_thread() {
+    let safe = Failsafe::builder()
+        .push(RetryPolicy::unlimited(Duration::from_millis(10)))
+        .build();
+    let mut person = Person::new();
+    person.set_always_fail(true);
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            sleep(Duration::from_millis(100));
+            token.cancel();
+        })
+    };
+    let start = Instant::now();
+    // the retries stop instead of going on forever
+    assert!(check_expected_error(
+        safe.run_with_cancellation(&mut person, &token),
+        "Cancelled"
+    ));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    canceller.join().unwrap();
+
+    // cancelling interrupts the delay between retries
+    let safe = Failsafe::builder()
+        .push(RetryPolicy::unlimited(Duration::from_secs(2)))
+        .build();
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            sleep(Duration::from_millis(100));
+            token.cancel();
+        })
+    };
+    let start = Instant::now();
+    assert!(check_expected_error(
+        safe.run_with_cancellation(&mut person, &token),
+        "Cancelled"
+    ));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    canceller.join().unwrap();
+}
+
+#[test]
+fn cancelling_closures() {
+    let safe = Failsafe::builder()
+        .push(RetryPolicy::unlimited(Duration::from_millis(10)))
+        .build();
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            sleep(Duration::from_millis(50));
+            token.cancel();
+        })
+    };
+    let start = Instant::now();
+    let result =
+        safe.get_with_cancellation(|_| Err::<(), _>(PersonError::NameFindingError), &token);
+    assert!(check_expected_error(result, "Cancelled"));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    canceller.join().unwrap();
+}
+
+#[test]
+fn cancelling_permit_waits() {
+    let cancel_soon = || {
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        std::thread::spawn(move || {
+            sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        token
+    };
+    let limiter = RateLimiter::new(LimiterType::Burst, 1, Duration::from_millis(1500))
+        .with_max_wait_time(Duration::from_secs(2));
+    let safe = Failsafe::builder().push(limiter).build();
+    let mut person = Person::new();
+    person.set_fail_pattern(vec![false]);
+    assert!(safe.run(&mut person).is_ok());
+    let start = Instant::now();
+    assert!(check_expected_error(
+        safe.run_with_cancellation(&mut person, &cancel_soon()),
+        "Cancelled"
+    ));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    let bulkhead = BulkheadPolicy::new(1).with_max_wait_time(Duration::from_secs(2));
+    let safe = Failsafe::builder().push(bulkhead.clone()).build();
+    let mut holder = bulkhead.clone();
+    holder.before_run().unwrap();
+    let start = Instant::now();
+    assert!(check_expected_error(
+        safe.run_with_cancellation(&mut person, &cancel_soon()),
+        "Cancelled"
+    ));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    holder.after_run();
+    assert_eq!(bulkhead.running(), 0);
+}
+
+#[test]
+fn timeout_cancels_runnable_and_inner_policies() {
+    // the runnable checks the token, and stops at the deadline
+    let safe = failsafe!([TimeoutPolicy; [Duration::from_millis(100)]]);
+    let mut person = Person::new();
+    person.set_fail_pattern(vec![false]);
+    person.set_wait_for(Duration::from_secs(2));
+    let start = Instant::now();
+    assert!(check_expected_error(safe.run(&mut person), "TimeoutError"));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // inner retries stop at the deadline
+    let safe = Failsafe::builder()
+        .push(TimeoutPolicy::new(Duration::from_millis(100)))
+        .push(RetryPolicy::unlimited(Duration::from_millis(10)))
+        .build();
+    let mut person = Person::new();
+    person.set_always_fail(true);
+    let start = Instant::now();
+    assert!(check_expected_error(safe.run(&mut person), "TimeoutError"));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // so do their delays
+    let safe = Failsafe::builder()
+        .push(TimeoutPolicy::new(Duration::from_millis(100)))
+        .push(RetryPolicy::unlimited(Duration::from_secs(2)))
+        .build();
+    let mut person = Person::new();
+    person.set_always_fail(true);
+    let start = Instant::now();
+    assert!(check_expected_error(safe.run(&mut person), "TimeoutError"));
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    // inner policies that ignore the deadline are timed out once they finish
+    let safe = Failsafe::builder()
+        .push(TimeoutPolicy::new(Duration::from_millis(50)).with_interrupt())
+        .push(RetryPolicy::new(2, Duration::from_millis(1)))
+        .build();
+    let result = safe.get(|| {
+        sleep(Duration::from_millis(100));
+        Ok::<_, PersonError>("Person".to_string())
+    });
+    assert!(check_expected_error(result, "TimeoutError"));
+
+    // while outer retries go on, every attempt gets its own deadline
+    let safe = failsafe!([
+        RetryPolicy; [2, Duration::from_millis(1)],
+        TimeoutPolicy; [Duration::from_millis(50)]
+    ]);
+    let mut person = Person::new();
+    person.set_fail_pattern(vec![false]);
+    person.set_wait_for(Duration::from_secs(2));
+    let start = Instant::now();
+    assert!(check_expected_error(safe.run(&mut person), "RetryError"));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn running_closures() {
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(1)]]);
+    let mut attempts = 0;
+    let value = safe.get(|| {
+        attempts += 1;
+        if attempts < 3 {
+            Err(PersonError::NameFindingError)
+        } else {
+            Ok(attempts * 10)
+        }
+    });
+    assert_eq!(value.unwrap(), 30);
+
+    let mut attempts = 0;
+    let result = safe.run_fn(|| {
+        attempts += 1;
+        Err::<(), _>(PersonError::NameFindingError)
+    });
+    assert_eq!(attempts, 3);
+    assert!(check_expected_error(result, "RetryError"));
+
+    let safe = failsafe!([TimeoutPolicy; [Duration::from_millis(100)]]);
+    // errors keep their type
+    match safe.run_fn(|| Err::<(), _>("unavailable")) {
+        Err(FailsafeError::RunnableError(e)) => assert_eq!(e, "unavailable"),
+        _ => panic!("expected a runnable error"),
+    }
+}
+
+#[test]
+fn typed_runnables() {
+    #[derive(Clone)]
+    struct Counter {
+        count: u32,
+        fail_until: u32,
+    }
+
+    impl Runnable<u32, PersonError> for Counter {
+        fn run(&mut self) -> Result<u32, PersonError> {
+            self.count += 1;
+            if self.count < self.fail_until {
+                Err(PersonError::NameFindingError)
+            } else {
+                Ok(self.count)
+            }
+        }
+
+        fn update(&mut self, _: &Box<dyn FallbackAble>) {}
+
+        fn detach(&self) -> Option<Box<dyn Interruptable<u32>>> {
+            Some(Box::new(self.clone()))
+        }
+
+        fn attach(&mut self, detached: &Box<dyn Interruptable<u32>>) {
+            *self = detached.as_any().downcast_ref::<Counter>().unwrap().clone();
+        }
+    }
+
+    impl Interruptable<u32> for Counter {
+        fn run(&mut self) -> Result<u32, Box<dyn Any + Send>> {
+            Runnable::run(self).map_err(|e| Box::new(e) as Box<dyn Any + Send>)
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+    }
+
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(1)].handle::<PersonError>()]);
+    let mut counter = Counter {
+        count: 0,
+        fail_until: 3,
+    };
+    let result: Result<u32, FailsafeError<PersonError>> = safe.run(&mut counter);
+    assert_eq!(result.unwrap(), 3);
+
+    let safe = failsafe!([TimeoutPolicy; [Duration::from_millis(100)].with_interrupt()]);
+    let mut counter = Counter {
+        count: 0,
+        fail_until: 2,
+    };
+    // the error of the detached copy is typed too
+    match safe.run(&mut counter) {
+        Err(FailsafeError::RunnableError(e)) => assert_eq!(e, PersonError::NameFindingError),
+        _ => panic!("expected a runnable error"),
+    }
+    assert_eq!(safe.run(&mut counter).unwrap(), 2);
+    assert_eq!(counter.count, 2);
+}
+
+#[test]
+fn event_listeners() {
+    let events = Arc::new(Mutex::new(vec![]));
+    let record = |name: &'static str| {
+        let events = events.clone();
+        move |e: &ExecutionEvent| {
+            let error = e.error.map(|e| format!("{:?}", e));
+            let error = error.as_deref().and_then(|e| e.split('(').next());
+            events.lock().unwrap().push(format!(
+                "{} {} #{} {}",
+                name,
+                e.policy,
+                e.attempt,
+                error.unwrap_or("-")
+            ));
+        }
+    };
+    let safe = Failsafe::builder()
+        .push(
+            FallbackPolicy::of_value(|| "No Name".to_string())
+                .on_fallback(record("fallback"))
+                .on_success(record("success")),
+        )
+        .push(
+            RetryPolicy::new(2, Duration::from_millis(1))
+                .on_retry(record("retry"))
+                .on_retries_exceeded(record("exceeded"))
+                .on_failure(record("failure")),
+        )
+        .push(
+            TimeoutPolicy::new(Duration::from_millis(50))
+                .on_timeout(record("timeout"))
+                .on_failure(record("failure")),
+        )
+        .on_complete(record("complete"))
+        .build();
+    let mut attempts = 0;
+    let name = safe.get(|| {
+        attempts += 1;
+        if attempts == 1 {
+            sleep(Duration::from_millis(100));
+        }
+        Err::<String, _>(PersonError::NameFindingError)
+    });
+    assert_eq!(name.unwrap(), "No Name");
+    assert_eq!(
+        *events.lock().unwrap(),
+        vec![
+            "timeout TimeoutPolicy #1 TimeoutError",
+            "failure TimeoutPolicy #1 TimeoutError",
+            "retry RetryPolicy #1 TimeoutError",
+            "failure TimeoutPolicy #2 RunnableError",
+            "exceeded RetryPolicy #2 RunnableError",
+            "failure RetryPolicy #2 RetryError",
+            "fallback FallbackPolicy #1 RetryError",
+            "success FallbackPolicy #1 -",
+            "complete FallbackPolicy #1 -",
+        ]
+    );
+}
+
+#[test]
+fn listeners_may_panic_and_use_the_failsafe() {
+    let failures = Arc::new(Mutex::new(0));
+    let completes = Arc::new(Mutex::new(0));
+    let cell = Arc::new(std::sync::OnceLock::<Failsafe>::new());
+    let safe = Failsafe::builder()
+        .push(RetryPolicy::new(1, Duration::from_millis(1)).on_failure({
+            let failures = failures.clone();
+            move |_| {
+                *failures.lock().unwrap() += 1;
+                if *failures.lock().unwrap() == 1 {
+                    panic!("listener failed");
+                }
+            }
+        }))
+        .on_complete({
+            let completes = completes.clone();
+            let cell = cell.clone();
+            move |_| {
+                *completes.lock().unwrap() += 1;
+                // runs the same pipeline, without being called for it again
+                assert!(cell
+                    .get()
+                    .unwrap()
+                    .run_fn(|| Ok::<_, PersonError>(()))
+                    .is_ok());
+            }
+        })
+        .build();
+    assert!(cell.set(safe).is_ok());
+    let safe = cell.get().unwrap();
+    let fail = || Err::<(), _>(PersonError::NameFindingError);
+    let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| safe.run_fn(fail)));
+    assert!(panicked.is_err());
+    assert!(safe.run_fn(fail).is_err());
+    assert_eq!(*failures.lock().unwrap(), 2);
+    assert_eq!(*completes.lock().unwrap(), 1);
+}
+
+#[test]
+fn execution_reports() {
+    let safe = Failsafe::builder()
+        .push(FallbackPolicy::of_value(|| "No Name".to_string()))
+        .push(RetryPolicy::new(2, Duration::from_millis(1)))
+        .push(TimeoutPolicy::new(Duration::from_millis(50)))
+        .build();
+    let mut attempts = 0;
+    let (name, report) = safe.get_with_report(|| {
+        attempts += 1;
+        if attempts == 1 {
+            sleep(Duration::from_millis(100));
+        }
+        Err::<String, _>(PersonError::NameFindingError)
+    });
+    assert_eq!(name.unwrap(), "No Name");
+    let errors: Vec<_> = report
+        .errors
+        .iter()
+        .map(|e| {
+            let error = format!("{:?}", e.error);
+            format!(
+                "{} #{} {}",
+                e.policy,
+                e.attempt,
+                error.split('(').next().unwrap()
+            )
+        })
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            "TimeoutPolicy #1 TimeoutError",
+            "RetryPolicy #1 TimeoutError",
+            "RetryPolicy #2 RunnableError",
+            "FallbackPolicy #1 RetryError",
+        ]
+    );
+    assert_eq!(report.attempts, 1);
+    assert!(report.elapsed >= Duration::from_millis(100));
+
+    let (result, report) = safe.get_with_report(|| Ok::<_, PersonError>("Jane".to_string()));
+    assert_eq!(result.unwrap(), "Jane");
+    assert!(report.errors.is_empty());
+}
+
+#[test]
+fn execution_context() {
+    let safe = failsafe!([
+        RetryPolicy; [3, Duration::from_millis(10)],
+        FallbackPolicy; [on_fallback!(Person::with_name("No Name"))]
+    ]);
+    let started = Instant::now();
+    let mut seen = vec![];
+    let result = safe.get_with_context(|context| {
+        assert!(context.started >= started && !context.is_cancelled());
+        let error = context.last_error.map(|e| format!("{:?}", e));
+        seen.push((context.attempt, context.fallback, error, context.elapsed()));
+        if context.attempt < 2 {
+            return Err(PersonError::NameFindingError);
+        }
+        Ok("Person".to_string())
+    });
+    assert_eq!(result.unwrap(), "Person");
+    assert_eq!(seen.len(), 2);
+    assert_eq!(
+        (seen[0].0, seen[0].1, seen[0].2.as_deref()),
+        (1, false, None)
+    );
+    assert_eq!(
+        (seen[1].0, seen[1].1, seen[1].2.as_deref()),
+        (2, true, Some("UsedFallback"))
+    );
+    assert!(seen[1].3 >= Duration::from_millis(10));
+
+    let safe = failsafe!([CircuitBreakerPolicy; [1, Duration::from_millis(20), 1]]);
+    let result = safe.get_with_context(|context| {
+        assert!(!context.trial);
+        Err::<(), _>(PersonError::NameFindingError)
+    });
+    assert!(result.is_err());
+    sleep(Duration::from_millis(30));
+    let trial = safe.get_with_context(|context| Ok::<_, PersonError>(context.trial));
+    assert!(trial.unwrap());
+}
+
+// a policy running the runnable once more when it fails, without a context
+#[derive(Clone, Default)]
+struct RunOnceMore {
+    policy_data: PolicyData,
+}
+
+impl Policy for RunOnceMore {
+    fn policy_data(&self) -> &PolicyData {
+        &self.policy_data
+    }
+
+    fn policy_data_mut(&mut self) -> &mut PolicyData {
+        &mut self.policy_data
+    }
+
+    fn name(&self) -> String {
+        "RunOnceMore".to_string()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn fork(&self) -> Box<dyn Policy> {
+        Box::new(self.clone())
+    }
+
+    fn policy_action(
+        &mut self,
+        runnable: &mut Box<&mut dyn Runnable>,
+        _: &FailsafeError,
+    ) -> Result<PolicyActionState, FailsafeError> {
+        match runnable.run() {
+            Ok(_) => Ok(PolicyActionState::Success),
+            Err(_) => Ok(PolicyActionState::Abort),
+        }
+    }
+}
+
+#[test]
+fn policies_may_run_closures_without_a_context() {
+    let safe = Failsafe::builder().push(RunOnceMore::default()).build();
+    let mut attempts = vec![];
+    let result = safe.get_with_context(|context| {
+        attempts.push(context.attempt);
+        if context.attempt < 2 {
+            Err(PersonError::NameFindingError)
+        } else {
+            Ok(context.attempt)
+        }
+    });
+    assert_eq!(result.unwrap(), 2);
+    assert_eq!(attempts, vec![1, 2]);
+}
+
+#[test]
+fn fallbacks_get_the_cause() {
+    let fallback = || {
+        FallbackPolicy::of_result(|error: &FailsafeError, context: &ExecutionContext| {
+            assert!(context.last_error.is_some());
+            match error {
+                FailsafeError::TimeoutError => Ok("Timed Out".to_string()),
+                // fallbacks may fail too
+                _ => Err(PersonError::Cancelled),
+            }
+        })
+    };
+    let safe = Failsafe::builder()
+        .push(fallback())
+        .push(TimeoutPolicy::new(Duration::from_millis(20)))
+        .build();
+    let name = safe.get(|| {
+        sleep(Duration::from_millis(40));
+        Ok::<_, PersonError>("Person".to_string())
+    });
+    assert_eq!(name.unwrap(), "Timed Out");
+    let name = safe.get(|| Err::<String, _>(PersonError::NameFindingError));
+    assert!(matches!(
+        name,
+        Err(FailsafeError::RunnableError(PersonError::Cancelled))
+    ));
+
+    // falling back on the runnable, depending on the error
+    let safe = Failsafe::builder()
+        .push(FallbackPolicy::of_update(
+            |error: &FailsafeError, context: &ExecutionContext| {
+                assert_eq!(context.attempt, 1);
+                match error {
+                    FailsafeError::RunnableError(_) => {
+                        Ok(Box::new(Person::with_name("No Name")) as Box<dyn FallbackAble>)
+                    }
+                    _ => Err(PersonError::NameFindingError),
+                }
+            },
+        ))
+        .build();
+    let mut person = Person::new();
+    person.set_always_fail(true);
+    let result = safe.run(&mut person);
+    assert!(check_expected_error(result, "UsedFallback"));
+    assert_eq!(person.name(), "No Name");
+
+    // errors of another type than the runnable's are kept boxed
+    let safe = Failsafe::builder()
+        .push(FallbackPolicy::of_result(
+            |_: &FailsafeError, _: &ExecutionContext| Err::<String, _>("cache miss"),
+        ))
+        .build();
+    match safe.get(|| Err::<String, _>(PersonError::NameFindingError)) {
+        Err(FailsafeError::FallbackError(e)) => {
+            assert_eq!(e.downcast_ref::<&str>(), Some(&"cache miss"))
+        }
+        other => panic!("expected a fallback error, got {:?}", other),
+    }
+}
+
+#[cfg(feature = "async")]
+struct AsyncPerson {
+    attempts: u32,
+    fail_until: u32,
+    wait_for: Duration,
+}
+
+#[cfg(feature = "async")]
+impl crate::asynchronous::AsyncRunnable<u32, PersonError> for AsyncPerson {
+    async fn run(&mut self) -> Result<u32, PersonError> {
+        self.attempts += 1;
+        tokio::time::sleep(self.wait_for).await;
+        if self.attempts < self.fail_until {
+            Err(PersonError::NameFindingError)
+        } else {
+            Ok(self.attempts)
+        }
+    }
+
+    fn set_context(&mut self, context: &ExecutionContext) {
+        assert_eq!(context.attempt, self.attempts + 1);
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_retry_and_fallback() {
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(1)]]);
+    let mut person = AsyncPerson {
+        attempts: 0,
+        fail_until: 3,
+        wait_for: Duration::from_millis(1),
+    };
+    assert_eq!(safe.run_async(&mut person).await.unwrap(), 3);
+
+    let safe = Failsafe::builder()
+        .push(FallbackPolicy::of_value(|| 0u32))
+        .push(RetryPolicy::new(2, Duration::from_millis(1)))
+        .build();
+    let mut person = AsyncPerson {
+        attempts: 0,
+        fail_until: 10,
+        wait_for: Duration::from_millis(1),
+    };
+    assert_eq!(safe.run_async(&mut person).await.unwrap(), 0);
+    assert_eq!(person.attempts, 2);
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_delays_do_not_block() {
+    let safe = failsafe!([RetryPolicy; [3, Duration::from_millis(100)]]);
+    let new_person = || AsyncPerson {
+        attempts: 0,
+        fail_until: 10,
+        wait_for: Duration::ZERO,
+    };
+    let (mut first, mut second) = (new_person(), new_person());
+    let start = Instant::now();
+    // both run on the same thread, their delays overlap
+    let (first, second) = tokio::join!(safe.run_async(&mut first), safe.run_async(&mut second));
+    assert!(check_expected_error(first, "RetryError"));
+    assert!(check_expected_error(second, "RetryError"));
+    assert!(start.elapsed() < Duration::from_millis(350));
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_timeout_drops_attempt() {
+    let safe = Arc::new(failsafe!([TimeoutPolicy; [Duration::from_millis(50)]]));
+    let mut person = AsyncPerson {
+        attempts: 0,
+        fail_until: 0,
+        wait_for: Duration::from_secs(10),
+    };
+    let start = Instant::now();
+    // executions can run on other tasks
+    let result = tokio::spawn(async move { safe.run_async(&mut person).await })
+        .await
+        .unwrap();
+    assert!(check_expected_error(result, "TimeoutError"));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_timeout_releases_inner_policies() {
+    // the permit of a timed out attempt doesn't stay taken
+    let bulkhead = BulkheadPolicy::new(1);
+    let safe = Failsafe::builder()
+        .push(RetryPolicy::new(2, Duration::from_millis(1)))
+        .push(TimeoutPolicy::new(Duration::from_millis(20)))
+        .push(bulkhead.clone())
+        .build();
+    let mut person = AsyncPerson {
+        attempts: 0,
+        fail_until: 0,
+        wait_for: Duration::from_millis(50),
+    };
+    let (result, report) = safe.run_async_with_report(&mut person).await;
+    assert!(check_expected_error(result, "RetryError"));
+    assert!(report
+        .errors
+        .iter()
+        .all(|e| matches!(e.error, FailsafeError::TimeoutError)));
+    assert_eq!(person.attempts, 2);
+    assert_eq!(bulkhead.running(), 0);
+
+    // a timed out trial fails, instead of keeping the breaker half-open
+    let breaker = CircuitBreakerPolicy::new(1, Duration::from_secs(10), 1);
+    let safe = Failsafe::builder()
+        .push(TimeoutPolicy::new(Duration::from_millis(20)))
+        .push(breaker.clone())
+        .build();
+    breaker.half_open();
+    let mut person = AsyncPerson {
+        attempts: 0,
+        fail_until: 0,
+        wait_for: Duration::from_millis(50),
+    };
+    assert!(check_expected_error(
+        safe.run_async(&mut person).await,
+        "TimeoutError"
+    ));
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Open);
+    breaker.half_open();
+    let mut person = AsyncPerson {
+        attempts: 0,
+        fail_until: 0,
+        wait_for: Duration::ZERO,
+    };
+    assert!(safe.run_async(&mut person).await.is_ok());
+    assert_eq!(breaker.circuit_breaker_state(), CircuitBreakerState::Closed);
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn async_bulkhead_wakes_waiting_executions() {
+    let bulkhead = BulkheadPolicy::new(1).with_max_wait_time(Duration::from_secs(1));
+    let safe = Failsafe::builder().push(bulkhead.clone()).build();
+    let new_person = || AsyncPerson {
+        attempts: 0,
+        fail_until: 0,
+        wait_for: Duration::from_millis(50),
+    };
+    let (mut first, mut second) = (new_person(), new_person());
+    let start = Instant::now();
+    let (first, second) = tokio::join!(safe.run_async(&mut first), safe.run_async(&mut second));
+    assert!(first.is_ok() && second.is_ok());
+    // the second starts when the first releases its permit, not when it stops waiting
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert_eq!(bulkhead.running(), 0);
+}
+
+#[cfg(feature = "async")]
+struct AsyncCounter {
+    runs: u32,
+}
+
+#[cfg(feature = "async")]
+impl crate::asynchronous::AsyncRunnable<u32, PersonError> for AsyncCounter {
+    async fn run(&mut self) -> Result<u32, PersonError> {
+        self.runs += 1;
+        if self.runs < 2 {
+            Err(PersonError::NameFindingError)
+        } else {
+            Ok(self.runs)
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn policies_cannot_run_async_runnables() {
+    // running the runnable from a policy would block the runtime, the policy gets an error instead
+    let safe = Failsafe::builder().push(RunOnceMore::default()).build();
+    let mut counter = AsyncCounter { runs: 0 };
+    assert!(matches!(
+        safe.run_async(&mut counter).await,
+        Err(FailsafeError::RunnableError(PersonError::NameFindingError))
+    ));
+    assert_eq!(counter.runs, 1);
+}
+
+#[cfg(feature = "async")]
+#[tokio::test]
+async fn cancelling_async_executions() {
+    let safe = Failsafe::builder()
+        .push(RetryPolicy::unlimited(Duration::from_millis(10)))
+        .build();
+    let mut person = AsyncPerson {
+        attempts: 0,
+        fail_until: u32::MAX,
+        wait_for: Duration::from_millis(1),
+    };
+    let token = CancellationToken::new();
+    let canceller = {
+        let token = token.clone();
+        std::thread::spawn(move || {
+            sleep(Duration::from_millis(50));
+            token.cancel();
+        })
+    };
+    let start = Instant::now();
+    let result = safe.run_async_with_cancellation(&mut person, &token).await;
+    assert!(check_expected_error(result, "Cancelled"));
+    assert!(start.elapsed() < Duration::from_secs(1));
+    canceller.join().unwrap();
 }